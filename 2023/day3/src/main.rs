@@ -1,19 +1,218 @@
+use std::collections::BTreeMap;
+
 fn main() {
+    // Usage: day3 [--symbols CHARS] [--signed] [--hex] [--wrap] [--dot] [--stream FILE]
+    let mut options = ParseOptions::default();
+    let mut is_variant = false;
+    let mut print_dot = false;
+    let mut stream_path: Option<String> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Restrict which chars count as symbols, e.g. `--symbols '#@'`
+            "--symbols" => {
+                let chars = args.next().expect("--symbols requires a value");
+                options.symbols = SymbolClass::only(&chars.chars().collect::<Vec<char>>());
+                is_variant = true;
+            }
+            "--signed" => {
+                options.numbers.signed = true;
+                is_variant = true;
+            }
+            "--hex" => {
+                options.numbers.radix = 16;
+                is_variant = true;
+            }
+            "--wrap" => {
+                options.wrap = true;
+                is_variant = true;
+            }
+            // Print the graph of parts in DOT format instead of the results
+            "--dot" => print_dot = true,
+            "--stream" => stream_path = Some(args.next().expect("--stream requires a file")),
+            other => panic!("Unknown argument: {}", other),
+        }
+    }
+
+    // Huge schematics are read row by row instead of being loaded into memory
+    if let Some(path) = stream_path {
+        let file = std::fs::File::open(&path).expect("Failed to open file");
+        let reader = std::io::BufReader::new(file);
+        let sum = streaming::compute(reader, &options).unwrap_or_else(|err| panic!("{}", err));
+        println!("Sum: {}", sum);
+        return;
+    }
+
     let input = include_str!("input.txt");
+
+    if print_dot {
+        let (numbers, symbols) =
+            parsing::parse_with(input, &options).unwrap_or_else(|err| panic!("{}", err));
+        let graph = graph::PartGraph::new(&numbers, &symbols, &options.topology(input));
+        print!("{}", graph.to_dot());
+        return;
+    }
+
     let sum = compute(input);
     println!("Sum: {}", sum);
+
+    let (numbers, symbols) = if is_variant {
+        let variant_sum = compute_with(input, &options).unwrap_or_else(|err| panic!("{}", err));
+        println!("Variant sum: {}", variant_sum);
+        parsing::parse_with(input, &options).unwrap_or_else(|err| panic!("{}", err))
+    } else {
+        parsing::parse(input)
+    };
+
+    let topology = options.topology(input);
+    let stats = symbol_stats(&numbers, &symbols, &topology).unwrap_or_else(|err| panic!("{}", err));
+    for (ch, stats) in stats {
+        println!(
+            "{}: occurrences={} numbers={} sum={}",
+            ch, stats.occurrences, stats.numbers, stats.sum
+        );
+    }
+
+    let graph = graph::PartGraph::new(&numbers, &symbols, &topology);
+    let components = graph.components().unwrap_or_else(|err| panic!("{}", err));
+    if let Some(largest) = components.iter().max_by_key(|c| c.size()) {
+        println!(
+            "Clusters: {}, largest: size={} sum={} product={:?}",
+            components.len(),
+            largest.size(),
+            largest.sum,
+            largest.product
+        );
+    }
 }
 
-fn compute(input: &str) -> u32 {
-    let (numbers, symbols) = parsing::parse(input);
+fn compute(input: &str) -> i64 {
+    compute_with(input, &ParseOptions::default()).expect("Failed to parse input")
+}
+
+fn compute_with(input: &str, options: &ParseOptions) -> Result<i64, parsing::ParseError> {
+    let (numbers, symbols) = parsing::parse_with(input, options)?;
+    let topology = options.topology(input);
 
     numbers
         .iter()
-        .filter(|n| is_adjusted_to_one_of_symbols(n, &symbols))
-        .map(|n| n.value)
-        .sum::<u32>()
+        .filter(|n| is_adjusted_to_one_of_symbols(n, &symbols, &topology))
+        .try_fold(0i64, checked_sum)
+}
+
+#[derive(Debug, Clone, Default)]
+struct ParseOptions {
+    symbols: SymbolClass,
+    numbers: NumberFormat,
+    // The schematic wraps around its edges, see Topology::Torus
+    wrap: bool,
+}
+
+impl ParseOptions {
+    fn topology(&self, input: &str) -> Topology {
+        if self.wrap {
+            Topology::torus_of(input)
+        } else {
+            Topology::Bounded
+        }
+    }
+}
+
+/// Describes what lies beyond the edges of the schematic.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+enum Topology {
+    // Nothing, positions next to the edges simply have fewer neighbours
+    #[default]
+    Bounded,
+    // The schematic wraps around, so x and y are taken modulo the grid size
+    // and a number may continue from the right edge to the left one.
+    Torus {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Topology {
+    fn torus_of(input: &str) -> Topology {
+        let width = input.lines().next().map_or(0, |line| line.chars().count());
+        let height = input.lines().count();
+        Topology::Torus { width, height }
+    }
+}
+
+/// Describes how runs of digits are turned into numbers.
+#[derive(Debug, Clone, Copy)]
+struct NumberFormat {
+    // A '-' right before a number is its sign rather than a symbol
+    signed: bool,
+    // With radix 16 runs of hex digits (0-9, a-f, A-F) are numbers, so these letters are not symbols
+    radix: u32,
 }
 
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            signed: false,
+            radix: 10,
+        }
+    }
+}
+
+/// Describes which non-digit characters of a schematic are symbols,
+/// which are empty cells and which must not appear at all.
+#[derive(Debug, Clone)]
+struct SymbolClass {
+    symbols: Vec<char>,
+    empty: Vec<char>,
+    invalid: Vec<char>,
+    // How to treat a char that is not listed in any of the sets above
+    other: CharKind,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CharKind {
+    Symbol,
+    Empty,
+    Invalid,
+}
+
+impl Default for SymbolClass {
+    // The rules of the original puzzle: '.' is empty, everything else is a symbol.
+    fn default() -> Self {
+        SymbolClass {
+            symbols: vec![],
+            empty: vec!['.'],
+            invalid: vec![],
+            other: CharKind::Symbol,
+        }
+    }
+}
+
+impl SymbolClass {
+    // Only the given chars are symbols, any other non-digit char is empty.
+    fn only(symbols: &[char]) -> Self {
+        SymbolClass {
+            symbols: symbols.to_vec(),
+            empty: vec![],
+            invalid: vec![],
+            other: CharKind::Empty,
+        }
+    }
+
+    fn classify(&self, ch: char) -> CharKind {
+        if self.symbols.contains(&ch) {
+            CharKind::Symbol
+        } else if self.empty.contains(&ch) {
+            CharKind::Empty
+        } else if self.invalid.contains(&ch) {
+            CharKind::Invalid
+        } else {
+            self.other
+        }
+    }
+}
+
+// Columns (x) are measured in chars, not bytes, so non-ASCII symbols take a single cell.
 #[derive(Debug, PartialEq, Clone, Copy)]
 struct Pos {
     x: usize,
@@ -22,21 +221,32 @@ struct Pos {
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 struct Number {
-    value: u32,
+    value: i64,
     pos: Pos,
     size: usize,
 }
 
 impl Number {
-    fn all_taken_positions(&self) -> Vec<Pos> {
+    fn all_taken_positions(&self, topology: &Topology) -> Vec<Pos> {
         let mut positions = Vec::with_capacity(self.size);
 
         for x in self.pos.x..(self.pos.x + self.size) {
+            let x = match topology {
+                Topology::Bounded => x,
+                Topology::Torus { width, .. } => x % width,
+            };
             positions.push(Pos { x, y: self.pos.y });
         }
 
         positions
     }
+
+    fn touches(&self, symbol: &Symbol, topology: &Topology) -> bool {
+        let nearby = symbol.all_nearby_positions(topology);
+        self.all_taken_positions(topology)
+            .iter()
+            .any(|pos| nearby.contains(pos))
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -47,7 +257,14 @@ struct Symbol {
 
 impl Symbol {
     // All the 8 positions around the symbol
-    fn all_nearby_positions(&self) -> Vec<Pos> {
+    fn all_nearby_positions(&self, topology: &Topology) -> Vec<Pos> {
+        match *topology {
+            Topology::Bounded => self.bounded_nearby_positions(),
+            Topology::Torus { width, height } => self.wrapped_nearby_positions(width, height),
+        }
+    }
+
+    fn bounded_nearby_positions(&self) -> Vec<Pos> {
         let mut positions = Vec::with_capacity(8);
 
         let minx = if self.pos.x == 0 { 0 } else { self.pos.x - 1 };
@@ -66,26 +283,198 @@ impl Symbol {
 
         positions
     }
+
+    // On small grids (less than 3 cells in a dimension) the wrapped positions overlap,
+    // so duplicates and the position of the symbol itself are skipped.
+    fn wrapped_nearby_positions(&self, width: usize, height: usize) -> Vec<Pos> {
+        let mut positions = Vec::with_capacity(8);
+
+        for dx in [width - 1, 0, 1] {
+            for dy in [height - 1, 0, 1] {
+                let x = (self.pos.x + dx) % width;
+                let y = (self.pos.y + dy) % height;
+                let pos = Pos { x, y };
+                if pos == self.pos || positions.contains(&pos) {
+                    continue;
+                }
+                positions.push(pos);
+            }
+        }
+
+        positions
+    }
 }
 
-fn is_adjusted_to_one_of_symbols(number: &Number, symbols: &[Symbol]) -> bool {
-    let number_positions = number.all_taken_positions();
+fn is_adjusted_to_one_of_symbols(number: &Number, symbols: &[Symbol], topology: &Topology) -> bool {
+    let number_positions = number.all_taken_positions(topology);
     let symbol_positions: Vec<Pos> = symbols
         .iter()
-        .flat_map(|s| s.all_nearby_positions())
+        .flat_map(|s| s.all_nearby_positions(topology))
         .collect();
     for number_pos in number_positions.iter() {
-        if symbol_positions.contains(&number_pos) {
+        if symbol_positions.contains(number_pos) {
             return true;
         }
     }
     false
 }
 
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+struct SymbolStats {
+    // How many times the symbol occurs in the schematic
+    occurrences: usize,
+    // How many numbers touch the symbol. A number touching two occurrences is counted twice.
+    numbers: usize,
+    // Sum of values of the touching numbers
+    sum: i64,
+}
+
+// Adds the value of the number to the sum, failing when the sum doesn't fit into i64
+fn checked_sum(sum: i64, number: &Number) -> Result<i64, parsing::ParseError> {
+    sum.checked_add(number.value)
+        .ok_or(parsing::ParseError::SumOverflow { pos: number.pos })
+}
+
+fn symbol_stats(
+    numbers: &[Number],
+    symbols: &[Symbol],
+    topology: &Topology,
+) -> Result<BTreeMap<char, SymbolStats>, parsing::ParseError> {
+    let mut stats: BTreeMap<char, SymbolStats> = BTreeMap::new();
+
+    for symbol in symbols {
+        let entry = stats.entry(symbol.value).or_default();
+        entry.occurrences += 1;
+        for number in numbers.iter().filter(|n| n.touches(symbol, topology)) {
+            entry.numbers += 1;
+            entry.sum = checked_sum(entry.sum, number)?;
+        }
+    }
+
+    Ok(stats)
+}
+
 mod parsing {
     use super::*;
 
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum ParseError {
+        InvalidChar {
+            ch: char,
+            pos: Pos,
+        },
+        Overflow {
+            text: String,
+            pos: Pos,
+        },
+        // Adding the number at `pos` makes the sum not fit into i64
+        SumOverflow {
+            pos: Pos,
+        },
+        // Rows which width (in chars) differs from the width of the first row
+        RaggedRows {
+            expected_width: usize,
+            rows: Vec<RaggedRow>,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub struct RaggedRow {
+        // 1-based line number
+        pub line: usize,
+        pub width: usize,
+    }
+
+    // Returns the rows of the schematic which width differs from the width of the first row.
+    pub fn find_ragged_rows<'a>(lines: impl Iterator<Item = &'a str>) -> Option<ParseError> {
+        let mut expected_width: Option<usize> = None;
+        let mut rows = vec![];
+
+        for (y, line) in lines.enumerate() {
+            let width = line.chars().count();
+            match expected_width {
+                None => expected_width = Some(width),
+                Some(expected) if expected != width => rows.push(RaggedRow { line: y + 1, width }),
+                Some(_) => {}
+            }
+        }
+
+        match expected_width {
+            Some(expected_width) if !rows.is_empty() => Some(ParseError::RaggedRows {
+                expected_width,
+                rows,
+            }),
+            _ => None,
+        }
+    }
+
+    impl std::fmt::Display for ParseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                ParseError::InvalidChar { ch, pos } => {
+                    write!(f, "Invalid char {:?} at x={}, y={}", ch, pos.x, pos.y)
+                }
+                ParseError::Overflow { text, pos } => write!(
+                    f,
+                    "Number {} at x={}, y={} does not fit into i64",
+                    text, pos.x, pos.y
+                ),
+                ParseError::SumOverflow { pos } => write!(
+                    f,
+                    "Sum does not fit into i64 after adding the number at x={}, y={}",
+                    pos.x, pos.y
+                ),
+                ParseError::RaggedRows {
+                    expected_width,
+                    rows,
+                } => {
+                    write!(f, "Rows must be {} chars wide, but got:", expected_width)?;
+                    for row in rows {
+                        write!(f, " line {} ({} chars)", row.line, row.width)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
     pub fn parse(input: &str) -> (Vec<Number>, Vec<Symbol>) {
+        parse_with(input, &ParseOptions::default()).expect("Failed to parse input")
+    }
+
+    pub fn parse_with(
+        input: &str,
+        options: &ParseOptions,
+    ) -> Result<(Vec<Number>, Vec<Symbol>), ParseError> {
+        if let Some(err) = find_ragged_rows(input.lines()) {
+            return Err(err);
+        }
+
+        let mut numbers: Vec<Number> = vec![];
+        let mut symbols: Vec<Symbol> = vec![];
+
+        for (y, line) in input.lines().enumerate() {
+            let (row_numbers, row_symbols) = parse_row(y, line, options)?;
+            numbers.extend(row_numbers);
+            symbols.extend(row_symbols);
+        }
+
+        Ok((numbers, symbols))
+    }
+
+    // Parses a single line of the schematic. Numbers never span multiple lines,
+    // so the rows can be parsed independently.
+    pub fn parse_row(
+        y: usize,
+        line: &str,
+        options: &ParseOptions,
+    ) -> Result<(Vec<Number>, Vec<Symbol>), ParseError> {
+        let ParseOptions {
+            symbols: class,
+            numbers: format,
+            ..
+        } = options;
+
         let mut numbers: Vec<Number> = vec![];
         let mut symbols: Vec<Symbol> = vec![];
 
@@ -97,16 +486,47 @@ mod parsing {
             None => {}
         };
 
-        for (y, line) in input.lines().enumerate() {
-            for (x, ch) in line.chars().enumerate() {
-                let out = state.feed_char(x, y, ch);
-                handle_output(out);
-            }
-            handle_output(state.flush());
+        // In the wrap mode a number may continue from the right edge to the left one,
+        // so the row is scanned cyclically starting from the first non-digit char.
+        let width = line.chars().count();
+        let start = if options.wrap {
+            line.chars()
+                .position(|ch| !ch.is_digit(format.radix))
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        for (x, ch) in line.chars().enumerate().cycle().skip(start).take(width) {
+            let cell = if ch.is_digit(format.radix) {
+                Cell::Digit(ch)
+            } else {
+                match class.classify(ch) {
+                    CharKind::Symbol if ch == '-' && format.signed => Cell::Sign(true),
+                    CharKind::Empty if ch == '-' && format.signed => Cell::Sign(false),
+                    CharKind::Symbol => Cell::Symbol(ch),
+                    CharKind::Empty => Cell::Empty,
+                    CharKind::Invalid => {
+                        let pos = Pos { x, y };
+                        return Err(ParseError::InvalidChar { ch, pos });
+                    }
+                }
+            };
+            let out = state.feed_cell(x, y, cell, format)?;
+            handle_output(out);
         }
-        handle_output(state.flush());
+        handle_output(state.flush(format)?);
 
-        (numbers, symbols)
+        Ok((numbers, symbols))
+    }
+
+    // A char of the schematic after it's classified according to the ParseOptions
+    enum Cell {
+        Digit(char),
+        Empty,
+        Symbol(char),
+        // A '-' in the signed mode. The flag tells if it's a symbol when no digit follows it.
+        Sign(bool),
     }
 
     #[derive(Debug, Default)]
@@ -115,6 +535,7 @@ mod parsing {
         Empty,
         Number(Pos, String),
         Symbol(Pos, char),
+        Sign(Pos, bool),
     }
 
     enum Output {
@@ -123,86 +544,67 @@ mod parsing {
     }
 
     impl StateMachine {
-        fn feed_char(&mut self, x: usize, y: usize, input_char: char) -> Option<Output> {
+        fn feed_cell(
+            &mut self,
+            x: usize,
+            y: usize,
+            cell: Cell,
+            format: &NumberFormat,
+        ) -> Result<Option<Output>, ParseError> {
             let current_state = std::mem::take(self);
 
-            let (new_state, res) = match current_state {
-                StateMachine::Empty => {
-                    match input_char {
-                        '0'..='9' => {
-                            // Start a new number
-                            let pos = Pos { x, y };
-                            let s = String::from(input_char);
-                            (StateMachine::Number(pos, s), None)
-                        }
-                        '.' => {
-                            // Do nothing, skip
-                            (StateMachine::Empty, None)
-                        }
-                        sym => {
-                            // Put a new symbol to the state
-                            let pos = Pos { x, y };
-                            (StateMachine::Symbol(pos, sym), None)
-                        }
-                    }
+            let (new_state, res) = match (current_state, cell) {
+                (StateMachine::Number(num_pos, mut num_str), Cell::Digit(digit)) => {
+                    // Attach a new digit to the current number
+                    num_str.push(digit);
+                    (StateMachine::Number(num_pos, num_str), None)
                 }
-                StateMachine::Number(num_pos, mut num_str) => {
-                    match input_char {
-                        '0'..='9' => {
-                            // Attach a new digit to the current number
-                            num_str.push(input_char);
-                            (StateMachine::Number(num_pos, num_str), None)
-                        }
-                        '.' => {
-                            // Flush the current number and set empty state
-                            (StateMachine::Empty, output_number(num_pos, num_str))
-                        }
-                        sym => {
-                            let sym_pos = Pos { x, y };
-                            (
-                                StateMachine::Symbol(sym_pos, sym),
-                                output_number(num_pos, num_str),
-                            )
-                        }
-                    }
+                (StateMachine::Sign(sign_pos, _), Cell::Digit(digit)) => {
+                    // Start a new negative number
+                    let s = format!("-{}", digit);
+                    (StateMachine::Number(sign_pos, s), None)
                 }
-                StateMachine::Symbol(sym_pos, sym_ch) => {
-                    match input_char {
-                        '0'..='9' => {
-                            // Start a new number
-                            let pos = Pos { x, y };
-                            let s = String::from(input_char);
-                            (StateMachine::Number(pos, s), output_symbol(sym_pos, sym_ch))
-                        }
-                        '.' => (StateMachine::Empty, output_symbol(sym_pos, sym_ch)),
-                        sym => {
-                            let pos = Pos { x, y };
-                            let new_state = StateMachine::Symbol(pos, sym);
-                            (new_state, output_symbol(sym_pos, sym_ch))
-                        }
-                    }
+                (mut current_state, cell) => {
+                    // Flush the current state and start a new one
+                    let pos = Pos { x, y };
+                    let new_state = match cell {
+                        Cell::Digit(digit) => StateMachine::Number(pos, String::from(digit)),
+                        Cell::Empty => StateMachine::Empty,
+                        Cell::Symbol(sym) => StateMachine::Symbol(pos, sym),
+                        Cell::Sign(is_symbol) => StateMachine::Sign(pos, is_symbol),
+                    };
+                    (new_state, current_state.flush(format)?)
                 }
             };
 
             *self = new_state;
-            res
+            Ok(res)
         }
 
-        fn flush(&mut self) -> Option<Output> {
+        fn flush(&mut self, format: &NumberFormat) -> Result<Option<Output>, ParseError> {
             let state = std::mem::take(self);
             match state {
-                StateMachine::Empty => None,
-                StateMachine::Symbol(pos, value) => output_symbol(pos, value),
-                StateMachine::Number(pos, s) => output_number(pos, s),
+                StateMachine::Empty => Ok(None),
+                StateMachine::Symbol(pos, value) => Ok(output_symbol(pos, value)),
+                StateMachine::Sign(pos, true) => Ok(output_symbol(pos, '-')),
+                StateMachine::Sign(_, false) => Ok(None),
+                StateMachine::Number(pos, s) => output_number(pos, s, format),
             }
         }
     }
 
-    fn output_number(pos: Pos, s: String) -> Option<Output> {
-        let size = s.len();
-        let value: u32 = s.parse().expect("Invalid number");
+    fn output_number(
+        pos: Pos,
+        s: String,
+        format: &NumberFormat,
+    ) -> Result<Option<Output>, ParseError> {
+        let size = s.chars().count();
+        let value = match i64::from_str_radix(&s, format.radix) {
+            Ok(value) => value,
+            Err(_) => return Err(ParseError::Overflow { text: s, pos }),
+        };
         let number = Number { pos, size, value };
-        Some(Output::Number(number))
+        Ok(Some(Output::Number(number)))
     }
 
     fn output_symbol(pos: Pos, value: char) -> Option<Output> {
@@ -210,10 +612,287 @@ mod parsing {
     }
 }
 
-#[test]
-fn should_compute() {
-    // Input from the example in the task
-    let input = "
+mod streaming {
+    use super::parsing::ParseError;
+    use super::*;
+    use std::io::BufRead;
+
+    #[derive(Debug)]
+    pub enum StreamError {
+        Io(std::io::Error),
+        Parse(ParseError),
+    }
+
+    impl std::fmt::Display for StreamError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                StreamError::Io(err) => write!(f, "IO error: {}", err),
+                StreamError::Parse(err) => write!(f, "{}", err),
+            }
+        }
+    }
+
+    impl From<std::io::Error> for StreamError {
+        fn from(err: std::io::Error) -> Self {
+            StreamError::Io(err)
+        }
+    }
+
+    impl From<ParseError> for StreamError {
+        fn from(err: ParseError) -> Self {
+            StreamError::Parse(err)
+        }
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct Row {
+        numbers: Vec<Number>,
+        symbols: Vec<Symbol>,
+    }
+
+    pub fn compute<R: BufRead>(reader: R, options: &ParseOptions) -> Result<i64, StreamError> {
+        // The first overflow is kept and returned once the reading is done
+        let mut sum: Result<i64, ParseError> = Ok(0);
+        part_numbers(reader, options, |number| {
+            sum = sum.clone().and_then(|sum| checked_sum(sum, &number));
+        })?;
+        Ok(sum?)
+    }
+
+    // Reads the schematic line by line and emits every part number as soon as
+    // the row below it is read. Only three rows (previous, current, next) are kept in memory.
+    // In the wrap mode the first two rows are kept as well, since the first row
+    // can only be solved when the last one is read.
+    pub fn part_numbers<R: BufRead>(
+        reader: R,
+        options: &ParseOptions,
+        mut emit: impl FnMut(Number),
+    ) -> Result<(), StreamError> {
+        let mut prev = Row::default();
+        let mut current: Option<Row> = None;
+        let mut first_rows: Vec<Row> = vec![];
+        let mut expected_width: Option<usize> = None;
+        let mut height = 0;
+
+        for (y, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            // Rows are not kept, so a ragged row is reported as soon as it's found
+            let width = line.chars().count();
+            match expected_width {
+                None => expected_width = Some(width),
+                Some(expected_width) if expected_width != width => {
+                    let rows = vec![parsing::RaggedRow { line: y + 1, width }];
+                    let err = ParseError::RaggedRows {
+                        expected_width,
+                        rows,
+                    };
+                    return Err(err.into());
+                }
+                Some(_) => {}
+            }
+
+            let (numbers, symbols) = parsing::parse_row(y, &line, options)?;
+            let next = Row { numbers, symbols };
+            height += 1;
+
+            // The real height is unknown until the last row is read, but the rows of the window
+            // are never more than one row apart, so any height past the next row works the same.
+            let topology = if options.wrap {
+                Topology::Torus { width, height }
+            } else {
+                Topology::Bounded
+            };
+
+            // The neighborhood of the current row is complete now
+            if let Some(current) = current.take() {
+                // Except the first row in the wrap mode, it touches the last row too
+                if !(options.wrap && y == 1) {
+                    emit_part_numbers(&prev, &current, &next, &topology, &mut emit);
+                }
+                prev = current;
+            }
+            if options.wrap && y < 2 {
+                first_rows.push(next.clone());
+            }
+            current = Some(next);
+        }
+
+        let Some(last) = current else {
+            return Ok(());
+        };
+
+        match expected_width {
+            Some(width) if options.wrap => {
+                let topology = Topology::Torus { width, height };
+                let first = &first_rows[0];
+                let second = first_rows.get(1).unwrap_or(first);
+                if height > 1 {
+                    emit_part_numbers(&prev, &last, first, &topology, &mut emit);
+                }
+                emit_part_numbers(&last, first, second, &topology, &mut emit);
+            }
+            _ => {
+                let topology = Topology::Bounded;
+                emit_part_numbers(&prev, &last, &Row::default(), &topology, &mut emit);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn emit_part_numbers(
+        prev: &Row,
+        current: &Row,
+        next: &Row,
+        topology: &Topology,
+        emit: &mut impl FnMut(Number),
+    ) {
+        for number in &current.numbers {
+            let is_part = [prev, current, next]
+                .iter()
+                .flat_map(|row| &row.symbols)
+                .any(|symbol| number.touches(symbol, topology));
+            if is_part {
+                emit(*number);
+            }
+        }
+    }
+}
+
+mod graph {
+    use super::*;
+
+    // Graph of part numbers, two numbers are connected when they touch the same symbol.
+    // Numbers that touch no symbol are not parts, so they are not included.
+    pub struct PartGraph {
+        pub parts: Vec<Number>,
+        pub edges: Vec<Edge>,
+    }
+
+    // Connects two parts (indexes in PartGraph::parts) through a symbol
+    #[derive(Debug, PartialEq)]
+    pub struct Edge {
+        pub a: usize,
+        pub b: usize,
+        pub symbol: Symbol,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Component {
+        pub parts: Vec<Number>,
+        pub sum: i64,
+        // None if the product does not fit into i64
+        pub product: Option<i64>,
+    }
+
+    impl Component {
+        pub fn size(&self) -> usize {
+            self.parts.len()
+        }
+    }
+
+    impl PartGraph {
+        pub fn new(numbers: &[Number], symbols: &[Symbol], topology: &Topology) -> Self {
+            let touched: Vec<Vec<usize>> = symbols
+                .iter()
+                .map(|symbol| {
+                    (0..numbers.len())
+                        .filter(|&i| numbers[i].touches(symbol, topology))
+                        .collect()
+                })
+                .collect();
+
+            // Map indexes of numbers to indexes of parts
+            let mut part_indexes: Vec<Option<usize>> = vec![None; numbers.len()];
+            let mut parts = vec![];
+            for (i, number) in numbers.iter().enumerate() {
+                if touched.iter().any(|indexes| indexes.contains(&i)) {
+                    part_indexes[i] = Some(parts.len());
+                    parts.push(*number);
+                }
+            }
+
+            let mut edges = vec![];
+            for (symbol, indexes) in symbols.iter().zip(&touched) {
+                for (n, &i) in indexes.iter().enumerate() {
+                    for &j in &indexes[n + 1..] {
+                        edges.push(Edge {
+                            a: part_indexes[i].expect("Touching number must be a part"),
+                            b: part_indexes[j].expect("Touching number must be a part"),
+                            symbol: *symbol,
+                        });
+                    }
+                }
+            }
+
+            PartGraph { parts, edges }
+        }
+
+        // Connected components in order of their first part
+        pub fn components(&self) -> Result<Vec<Component>, parsing::ParseError> {
+            // Union-find over the parts
+            let mut parents: Vec<usize> = (0..self.parts.len()).collect();
+            for edge in &self.edges {
+                let root_a = find_root(&mut parents, edge.a);
+                let root_b = find_root(&mut parents, edge.b);
+                if root_a != root_b {
+                    parents[root_a.max(root_b)] = root_a.min(root_b);
+                }
+            }
+
+            let mut groups: BTreeMap<usize, Vec<Number>> = BTreeMap::new();
+            for (i, part) in self.parts.iter().enumerate() {
+                let root = find_root(&mut parents, i);
+                groups.entry(root).or_default().push(*part);
+            }
+
+            groups
+                .into_values()
+                .map(|parts| {
+                    let sum = parts.iter().try_fold(0i64, checked_sum)?;
+                    let product = parts
+                        .iter()
+                        .try_fold(1i64, |acc, p| acc.checked_mul(p.value));
+                    Ok(Component {
+                        parts,
+                        sum,
+                        product,
+                    })
+                })
+                .collect()
+        }
+
+        pub fn to_dot(&self) -> String {
+            let mut dot = String::from("graph parts {\n");
+            for (i, part) in self.parts.iter().enumerate() {
+                dot.push_str(&format!("    n{} [label=\"{}\"];\n", i, part.value));
+            }
+            for edge in &self.edges {
+                dot.push_str(&format!(
+                    "    n{} -- n{} [label={:?}];\n",
+                    edge.a,
+                    edge.b,
+                    edge.symbol.value.to_string()
+                ));
+            }
+            dot.push_str("}\n");
+            dot
+        }
+    }
+
+    fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+}
+
+// Input from the example in the task
+#[cfg(test)]
+const EXAMPLE: &str = "\
 467..114..
 ...*......
 ..35..633.
@@ -223,9 +902,225 @@ fn should_compute() {
 ..592.....
 ......755.
 ...$.*....
-.664.598..
-"
-    .trim();
+.664.598..";
+
+#[test]
+fn should_compute() {
+    let input = EXAMPLE;
     let sum = compute(input);
     assert_eq!(sum, 4361);
 }
+
+#[test]
+fn should_compute_symbol_stats() {
+    let input = EXAMPLE;
+    let (numbers, symbols) = parsing::parse(input);
+    let stats = symbol_stats(&numbers, &symbols, &Topology::Bounded).unwrap();
+
+    let star = SymbolStats {
+        occurrences: 3,
+        numbers: 5,
+        sum: 467 + 35 + 617 + 755 + 598,
+    };
+    assert_eq!(stats[&'*'], star);
+    assert_eq!(stats[&'#'].sum, 633);
+
+    // Variant: only '#' counts as a symbol
+    let options = ParseOptions {
+        symbols: SymbolClass::only(&['#']),
+        ..ParseOptions::default()
+    };
+    assert_eq!(compute_with(input, &options), Ok(633));
+
+    // Variant: '$' is not allowed
+    let options = ParseOptions {
+        symbols: SymbolClass {
+            invalid: vec!['$'],
+            ..SymbolClass::default()
+        },
+        ..ParseOptions::default()
+    };
+    let err = parsing::ParseError::InvalidChar {
+        ch: '$',
+        pos: Pos { x: 3, y: 8 },
+    };
+    assert_eq!(compute_with(input, &options), Err(err));
+}
+
+#[test]
+fn should_compute_streaming() {
+    let input = EXAMPLE;
+
+    let mut parts = vec![];
+    let options = ParseOptions::default();
+    streaming::part_numbers(input.as_bytes(), &options, |n| parts.push(n.value)).unwrap();
+    assert_eq!(parts, vec![467, 35, 633, 617, 592, 755, 664, 598]);
+
+    let real_input = include_str!("input.txt");
+    let sum = streaming::compute(real_input.as_bytes(), &options).unwrap();
+    assert_eq!(sum, compute(real_input));
+}
+
+#[test]
+fn should_support_unicode_and_detect_ragged_rows() {
+    let input = "
+é.12.
+..§..
+....7
+"
+    .trim();
+    assert_eq!(compute(input), 12);
+
+    let ragged = "
+467..114..
+...*.....
+..35..633.
+......#....
+"
+    .trim();
+    let err = parsing::ParseError::RaggedRows {
+        expected_width: 10,
+        rows: vec![
+            parsing::RaggedRow { line: 2, width: 9 },
+            parsing::RaggedRow { line: 4, width: 11 },
+        ],
+    };
+    let options = ParseOptions::default();
+    assert_eq!(parsing::parse_with(ragged, &options), Err(err));
+
+    let result = streaming::compute(ragged.as_bytes(), &options);
+    assert!(matches!(
+        result,
+        Err(streaming::StreamError::Parse(
+            parsing::ParseError::RaggedRows { .. }
+        ))
+    ));
+}
+
+#[test]
+fn should_parse_signed_and_hex_numbers() {
+    let input = "
+.-12..
+....*.
+-7-...
+"
+    .trim();
+    assert_eq!(compute(input), 12 + 7);
+
+    let signed = ParseOptions {
+        numbers: NumberFormat {
+            signed: true,
+            ..NumberFormat::default()
+        },
+        ..ParseOptions::default()
+    };
+    assert_eq!(compute_with(input, &signed), Ok(-12 - 7));
+
+    let hex = ParseOptions {
+        numbers: NumberFormat {
+            radix: 16,
+            ..NumberFormat::default()
+        },
+        ..ParseOptions::default()
+    };
+    assert_eq!(compute_with("fF.\n..#", &hex), Ok(255));
+
+    let err = parsing::ParseError::Overflow {
+        text: "99999999999999999999".to_string(),
+        pos: Pos { x: 1, y: 0 },
+    };
+    assert_eq!(compute_with("*99999999999999999999", &signed), Err(err));
+
+    let input = "9223372036854775807*9223372036854775807";
+    let err = parsing::ParseError::SumOverflow {
+        pos: Pos { x: 20, y: 0 },
+    };
+    assert_eq!(
+        compute_with(input, &ParseOptions::default()),
+        Err(err.clone())
+    );
+    let streamed = streaming::compute(input.as_bytes(), &ParseOptions::default());
+    assert!(matches!(streamed, Err(streaming::StreamError::Parse(e)) if e == err));
+    let (numbers, symbols) = parsing::parse(input);
+    assert_eq!(
+        symbol_stats(&numbers, &symbols, &Topology::Bounded),
+        Err(err.clone())
+    );
+    let graph = graph::PartGraph::new(&numbers, &symbols, &Topology::Bounded);
+    assert_eq!(graph.components(), Err(err));
+}
+
+#[test]
+fn should_compute_wrapped() {
+    let input = "
+3.1
+...
+.*.
+"
+    .trim();
+    assert_eq!(compute(input), 0);
+
+    // "1" at the right edge continues with "3" at the left edge,
+    // and the last row touches the first one.
+    let options = ParseOptions {
+        wrap: true,
+        ..ParseOptions::default()
+    };
+    let (numbers, _) = parsing::parse_with(input, &options).unwrap();
+    let number = Number {
+        value: 13,
+        pos: Pos { x: 2, y: 0 },
+        size: 2,
+    };
+    assert_eq!(numbers, vec![number]);
+    let topology = options.topology(input);
+    let positions = vec![Pos { x: 2, y: 0 }, Pos { x: 0, y: 0 }];
+    assert_eq!(number.all_taken_positions(&topology), positions);
+
+    assert_eq!(compute_with(input, &options), Ok(13));
+    assert_eq!(streaming::compute(input.as_bytes(), &options).unwrap(), 13);
+
+    let wrapped_sum = compute_with(EXAMPLE, &options).unwrap();
+    assert_eq!(wrapped_sum, 4361);
+    assert_eq!(
+        streaming::compute(EXAMPLE.as_bytes(), &options).unwrap(),
+        wrapped_sum
+    );
+
+    let real_input = include_str!("input.txt");
+    assert_eq!(
+        streaming::compute(real_input.as_bytes(), &options).unwrap(),
+        compute_with(real_input, &options).unwrap()
+    );
+}
+
+#[test]
+fn should_build_part_graph() {
+    let input = EXAMPLE;
+    let (numbers, symbols) = parsing::parse(input);
+
+    // The gear rule: '*' connecting exactly two parts
+    let stars: Vec<Symbol> = symbols.iter().filter(|s| s.value == '*').copied().collect();
+    let graph = graph::PartGraph::new(&numbers, &stars, &Topology::Bounded);
+    let gear_ratios: i64 = graph
+        .components()
+        .unwrap()
+        .iter()
+        .filter(|c| c.size() == 2)
+        .filter_map(|c| c.product)
+        .sum();
+    assert_eq!(gear_ratios, 467835);
+
+    let graph = graph::PartGraph::new(&numbers, &symbols, &Topology::Bounded);
+    let sizes: Vec<usize> = graph
+        .components()
+        .unwrap()
+        .iter()
+        .map(|c| c.size())
+        .collect();
+    assert_eq!(sizes, vec![2, 1, 1, 1, 2, 1]);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("graph parts {\n    n0 [label=\"467\"];\n"));
+    assert!(dot.contains("    n0 -- n1 [label=\"*\"];\n"));
+}
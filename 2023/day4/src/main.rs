@@ -1,40 +1,580 @@
+use std::collections::HashSet;
+
 fn main() {
     let input = include_str!("input.txt");
 
-    let result1 = compute_part1(input);
+    // `day4 generate [--cards=N] [--winning=N] [--player=N] [--max=N] [--weights=W0,W1,..] [--seed=N]`
+    // prints a generated input and its answers instead of solving the puzzle
+    if std::env::args().nth(1).as_deref() == Some("generate") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let config = generator::Config::from_args(&args).unwrap_or_else(|err| panic!("{}", err));
+        let generated = generator::generate(&config).unwrap_or_else(|err| panic!("{}", err));
+        print!("{}", generated.input);
+        eprintln!("Part1 Answer: {:?}", generated.part1);
+        eprintln!("Part2 Answer: {:?}", generated.part2);
+        return;
+    }
+
+    let mut options = Part2Options::default();
+    let mut scoring: Option<(&str, ScoringRule)> = None;
+    let mut trace_format: Option<String> = None;
+    // The arguments which change Part 2, they label its extra result
+    let mut part2_args: Vec<&str> = Vec::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    for arg in &args {
+        match arg.as_str() {
+            // Print how the copies cascade instead of the results,
+            // e.g. `--trace=table`, `--trace=dot` or `--trace=mermaid`
+            trace if trace.starts_with("--trace=") => {
+                trace_format = Some(trace["--trace=".len()..].to_string());
+                continue;
+            }
+            // E.g. `--scoring=linear`, `--scoring=table:1,3,5` or `--scoring='expr:n * n'`
+            rule if rule.starts_with("--scoring=") => {
+                let text = &rule["--scoring=".len()..];
+                let rule = ScoringRule::parse(text).unwrap_or_else(|err| panic!("{}", err));
+                scoring = Some((text, rule));
+                continue;
+            }
+            // E.g. `--window=fixed:3` or `--window=scaled:2`
+            window if window.starts_with("--window=") => {
+                let window = CopyWindow::parse(&window["--window=".len()..]);
+                options.window = window.unwrap_or_else(|err| panic!("{}", err));
+            }
+            // Copies past the end of the table are an error instead of being ignored
+            "--strict" => options.bounds = BoundsPolicy::Error,
+            // Copies go to the cards with the next ids rather than on the next lines
+            "--by-id" => options.indexing = Indexing::ById,
+            "--backward" => options.algorithm = Algorithm::Backward,
+            other => panic!("Unknown argument: {}", other),
+        }
+        part2_args.push(arg);
+    }
+
+    if let Some(format) = trace_format {
+        let traces = trace_part2(input, &options).unwrap_or_else(|err| panic!("{}", err));
+        let output = match format.as_str() {
+            "table" => format_trace_table(&traces),
+            "dot" => format_trace_dot(&traces),
+            "mermaid" => format_trace_mermaid(&traces),
+            other => panic!("Unknown trace format: {}", other),
+        };
+        print!("{}", output);
+        return;
+    }
+
+    let result1 = compute_part1(input).unwrap_or_else(|err| panic!("{}", err));
     println!("Part1 Result: {}", result1);
 
-    let result2 = compute_part2(input);
+    if let Some((text, rule)) = scoring {
+        let result = compute_part1_with(input, &rule).unwrap_or_else(|err| panic!("{}", err));
+        println!("Part1 Result ({}): {}", text, result);
+    }
+
+    let cards = parse_cards(input).unwrap_or_else(|err| panic!("{}", err));
+    for issue in validate(&cards) {
+        eprintln!("Warning: {}", issue);
+    }
+
+    let result2 = compute_part2(input).unwrap_or_else(|err| panic!("{}", err));
     println!("Part2 Result: {}", result2);
+
+    if !part2_args.is_empty() {
+        let result = compute_part2_with(input, &options).unwrap_or_else(|err| panic!("{}", err));
+        println!("Part2 Result ({}): {}", part2_args.join(" "), result);
+    }
 }
 
-fn compute_part1(input: &str) -> u32 {
-    let total_points = input
-        .lines()
-        .map(Card::parse)
-        .map(|card| card.count_points())
-        .sum::<u32>();
-    total_points
+fn compute_part1(input: &str) -> Result<u64, Error> {
+    compute_part1_with(input, &ScoringRule::default())
+}
+
+fn compute_part1_with(input: &str, rule: &ScoringRule) -> Result<u64, Error> {
+    let mut total_points: u64 = 0;
+    for card in parse_cards(input)? {
+        let points = card.count_points(rule)?;
+        total_points = total_points
+            .checked_add(points)
+            .ok_or(Error::TotalOverflow)?;
+    }
+    Ok(total_points)
+}
+
+fn compute_part2(input: &str) -> Result<u64, Error> {
+    compute_part2_with(input, &Part2Options::default())
+}
+
+fn compute_part2_with(input: &str, options: &Part2Options) -> Result<u64, Error> {
+    let cards = parse_cards(input)?;
+    let cards = order_cards(&cards, options)?;
+
+    match options.algorithm {
+        Algorithm::Forward => {
+            let cards_count = propagate_copies(&cards, options, |_, _, _| {})?;
+            cards_count
+                .iter()
+                .try_fold(0u64, |total, count| total.checked_add(*count))
+                .ok_or(Error::TotalOverflow)
+        }
+        Algorithm::Backward => count_backward(&cards, options),
+    }
+}
+
+// total(i) = 1 + Σ total(j) over the cards j won by card i, is the number of cards
+// one instance of card i turns into. The cards only win copies of the later cards,
+// so going from the last card to the first one every total(j) is already known.
+// The won cards form a contiguous range, so the sums are taken from the suffix sums
+// and every card is processed in O(1) regardless of the number of matches.
+fn count_backward(cards: &[&Card], options: &Part2Options) -> Result<u64, Error> {
+    // The targets are checked from the first card, so the same card is reported as going forward
+    let targets = (0..cards.len())
+        .map(|card_index| copy_targets(cards, card_index, options))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // suffix_sums[i] = Σ total(j) for j >= i
+    let mut suffix_sums: Vec<u64> = vec![0; cards.len() + 1];
+
+    for (card_index, targets) in targets.into_iter().enumerate().rev() {
+        // Every suffix sum is part of the answer, so an overflow here means the answer overflows
+        let total = (suffix_sums[targets.start] - suffix_sums[targets.end])
+            .checked_add(1)
+            .ok_or_else(|| overflow_error(cards, options))?;
+        suffix_sums[card_index] = suffix_sums[card_index + 1]
+            .checked_add(total)
+            .ok_or_else(|| overflow_error(cards, options))?;
+    }
+
+    Ok(suffix_sums[0])
+}
+
+// The answer doesn't fit into u64. Going forward tells whether a count of cards
+// or only the total overflows, and which card overflows first.
+fn overflow_error(cards: &[&Card], options: &Part2Options) -> Error {
+    match propagate_copies(cards, options, |_, _, _| {}) {
+        Err(err) => err,
+        Ok(_) => Error::TotalOverflow,
+    }
 }
 
-fn compute_part2(input: &str) -> u32 {
-    let cards = input.lines().map(Card::parse).collect::<Vec<Card>>();
-    let mut cards_count = vec![1; cards.len()];
+// Returns how many instances of every (ordered) card there are in the end.
+// `on_copies` is called with the index of the winning card, the index of the won card
+// and the number of won copies.
+fn propagate_copies(
+    cards: &[&Card],
+    options: &Part2Options,
+    mut on_copies: impl FnMut(usize, usize, u64),
+) -> Result<Vec<u64>, Error> {
+    let mut cards_count: Vec<u64> = vec![1; cards.len()];
 
-    for (card_index, card) in cards.iter().enumerate() {
+    for card_index in 0..cards.len() {
         let copies_count = cards_count[card_index];
-        let matches = card.count_matches();
 
         // Add copies for the cards to the next cards
-        // TODO: clamp the bounds?
-        let from_index = card_index + 1;
-        let to_index = card_index + 1 + matches as usize;
-        for i in from_index..to_index {
-            cards_count[i] += copies_count
+        let targets = copy_targets(cards, card_index, options)?;
+        let from_index = targets.start;
+        for (i, count) in cards_count[targets].iter_mut().enumerate() {
+            *count = count
+                .checked_add(copies_count)
+                .ok_or(Error::CountOverflow {
+                    card_id: cards[from_index + i].id,
+                })?;
+            on_copies(card_index, from_index + i, copies_count);
+        }
+    }
+
+    Ok(cards_count)
+}
+
+#[derive(Debug, PartialEq)]
+struct CardTrace {
+    card_id: usize,
+    // The original card and all its copies
+    instances: u64,
+    // Ids of the earlier cards with the number of copies they contributed
+    contributions: Vec<(usize, u64)>,
+    // The winning numbers the player has on the card
+    matched: Vec<u32>,
+}
+
+fn trace_part2(input: &str, options: &Part2Options) -> Result<Vec<CardTrace>, Error> {
+    let cards = parse_cards(input)?;
+    let cards = order_cards(&cards, options)?;
+
+    let mut contributions: Vec<Vec<(usize, u64)>> = vec![vec![]; cards.len()];
+    let cards_count = propagate_copies(&cards, options, |from, to, copies| {
+        contributions[to].push((cards[from].id, copies));
+    })?;
+
+    let traces = cards
+        .iter()
+        .zip(cards_count)
+        .zip(contributions)
+        .map(|((card, instances), contributions)| CardTrace {
+            card_id: card.id,
+            instances,
+            contributions,
+            matched: card.matched_numbers(),
+        })
+        .collect();
+    Ok(traces)
+}
+
+fn format_trace_table(traces: &[CardTrace]) -> String {
+    let mut table = format!(
+        "{:>6} | {:>12} | {:<30} | {}\n",
+        "Card", "Instances", "Matched", "Copies from"
+    );
+    for trace in traces {
+        let matched = trace
+            .matched
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        let sources = trace
+            .contributions
+            .iter()
+            .map(|(card_id, copies)| format!("{} (+{})", card_id, copies))
+            .collect::<Vec<String>>()
+            .join(", ");
+        table.push_str(&format!(
+            "{:>6} | {:>12} | {:<30} | {}\n",
+            trace.card_id, trace.instances, matched, sources
+        ));
+    }
+    table
+}
+
+fn format_trace_dot(traces: &[CardTrace]) -> String {
+    let mut dot = String::from("digraph copies {\n");
+    for trace in traces {
+        dot.push_str(&format!(
+            "    c{} [label=\"Card {}\\n{} instances\"];\n",
+            trace.card_id, trace.card_id, trace.instances
+        ));
+        for (from_id, copies) in &trace.contributions {
+            dot.push_str(&format!(
+                "    c{} -> c{} [label=\"{}\"];\n",
+                from_id, trace.card_id, copies
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn format_trace_mermaid(traces: &[CardTrace]) -> String {
+    let mut mermaid = String::from("graph LR\n");
+    for trace in traces {
+        mermaid.push_str(&format!(
+            "    c{}[\"Card {}: {}\"]\n",
+            trace.card_id, trace.card_id, trace.instances
+        ));
+        for (from_id, copies) in &trace.contributions {
+            mermaid.push_str(&format!(
+                "    c{} -->|{}| c{}\n",
+                from_id, copies, trace.card_id
+            ));
+        }
+    }
+    mermaid
+}
+
+// How many points a card with N matches is worth. A card without matches is worth nothing.
+#[derive(Debug, Clone, PartialEq, Default)]
+enum ScoringRule {
+    // 1 point for the first match, doubled for every next one
+    #[default]
+    Doubling,
+    // 1 point for every match
+    Linear,
+    // 1, 1, 2, 3, 5, 8, ...
+    Fibonacci,
+    // Points for 1, 2, 3, ... matches
+    Table(Vec<u64>),
+    // Arithmetic expression of `n` (the number of matches) with + - * / ^ and parentheses
+    Expression(expression::Expr),
+}
+
+impl ScoringRule {
+    fn parse(text: &str) -> Result<ScoringRule, String> {
+        match text.split_once(':') {
+            None if text == "doubling" => Ok(ScoringRule::Doubling),
+            None if text == "linear" => Ok(ScoringRule::Linear),
+            None if text == "fibonacci" => Ok(ScoringRule::Fibonacci),
+            Some(("table", values)) => values
+                .split(',')
+                .map(|value| value.trim().parse::<u64>())
+                .collect::<Result<Vec<u64>, _>>()
+                .map(ScoringRule::Table)
+                .map_err(|err| format!("Invalid scoring table {:?}: {}", values, err)),
+            Some(("expr", expr)) => expression::parse(expr)
+                .map(ScoringRule::Expression)
+                .map_err(|err| format!("Invalid scoring expression {:?}: {}", expr, err)),
+            _ => Err(format!("Unknown scoring rule: {}", text)),
+        }
+    }
+
+    // None if the points don't fit into u64 or can't be computed
+    fn points(&self, matches: u32) -> Option<u64> {
+        if matches == 0 {
+            return Some(0);
+        }
+        match self {
+            ScoringRule::Doubling => 2u64.checked_pow(matches - 1),
+            ScoringRule::Linear => Some(matches as u64),
+            ScoringRule::Fibonacci => {
+                let (mut prev, mut current) = (0u64, 1u64);
+                for _ in 1..matches {
+                    (prev, current) = (current, prev.checked_add(current)?);
+                }
+                Some(current)
+            }
+            ScoringRule::Table(points) => points.get(matches as usize - 1).copied(),
+            ScoringRule::Expression(expr) => expr.eval(matches as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Part2Options {
+    bounds: BoundsPolicy,
+    indexing: Indexing,
+    window: CopyWindow,
+    algorithm: Algorithm,
+}
+
+// Two independent ways to get the same answer, so one can cross-check the other
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Algorithm {
+    // Add copies of every card to the cards it wins, from the first card to the last one
+    #[default]
+    Forward,
+    // Count how many cards every card turns into, from the last card to the first one
+    Backward,
+}
+
+// How many of the next cards a card with matches wins copies of
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyWindow {
+    // The number of matches multiplied by k, the original puzzle uses k = 1
+    Scaled(usize),
+    // Always N cards, no matter how many matches there are
+    Fixed(usize),
+}
+
+impl Default for CopyWindow {
+    fn default() -> Self {
+        CopyWindow::Scaled(1)
+    }
+}
+
+impl CopyWindow {
+    fn parse(text: &str) -> Result<CopyWindow, String> {
+        let invalid = || format!("Invalid copy window: {}", text);
+        let (kind, size) = text.split_once(':').ok_or_else(invalid)?;
+        let size: usize = size.parse().map_err(|_| invalid())?;
+        match kind {
+            "scaled" => Ok(CopyWindow::Scaled(size)),
+            "fixed" => Ok(CopyWindow::Fixed(size)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn size(&self, matches: u32) -> usize {
+        match *self {
+            _ if matches == 0 => 0,
+            CopyWindow::Scaled(k) => (matches as usize).saturating_mul(k),
+            CopyWindow::Fixed(n) => n,
+        }
+    }
+}
+
+// What to do when a card wins copies of cards past the end of the table
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum BoundsPolicy {
+    // Ignore the copies that don't fit into the table
+    #[default]
+    Clamp,
+    Error,
+}
+
+// How to find the cards which copies are won
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Indexing {
+    // The next cards in the input, regardless of their ids
+    #[default]
+    ByLine,
+    // The cards with the next ids. Missing ids are skipped, duplicated ids are an error.
+    ById,
+}
+
+// Puts the cards in the order in which copies are propagated
+fn order_cards<'a>(cards: &'a [Card], options: &Part2Options) -> Result<Vec<&'a Card>, Error> {
+    let mut ordered: Vec<&Card> = cards.iter().collect();
+    if options.indexing == Indexing::ById {
+        ordered.sort_by_key(|card| card.id);
+        if let Some(pair) = ordered.windows(2).find(|pair| pair[0].id == pair[1].id) {
+            return Err(Error::DuplicateId {
+                card_id: pair[0].id,
+            });
+        }
+    }
+    Ok(ordered)
+}
+
+// Indexes of the (ordered) cards which copies are won by the card at `card_index`
+fn copy_targets(
+    cards: &[&Card],
+    card_index: usize,
+    options: &Part2Options,
+) -> Result<std::ops::Range<usize>, Error> {
+    let card = cards[card_index];
+    let matches = card.count_matches();
+    let window = options.window.size(matches);
+    let from_index = card_index + 1;
+
+    let (to_index, is_past_end) = match options.indexing {
+        Indexing::ByLine => {
+            let to_index = from_index.saturating_add(window);
+            (to_index.min(cards.len()), to_index > cards.len())
+        }
+        Indexing::ById => {
+            // The cards are sorted by id, so the cards with the next ids follow the current one
+            let last_id = card.id.saturating_add(window);
+            let to_index = cards.partition_point(|c| c.id <= last_id);
+            let max_id = cards.last().map_or(0, |c| c.id);
+            (to_index, last_id > max_id)
+        }
+    };
+
+    if is_past_end && options.bounds == BoundsPolicy::Error {
+        let card_id = card.id;
+        return Err(Error::CopiesPastEnd { card_id, matches });
+    }
+    Ok(from_index..to_index)
+}
+
+#[derive(Debug, PartialEq)]
+enum Issue {
+    // Ids between 1 and the greatest id which are not used by any card, `from` and `to` included
+    MissingIds { from: usize, to: usize },
+    DuplicateId { card_id: usize, line: usize },
+    // The id is less than the id of the card on the previous line
+    OutOfOrder { card_id: usize, line: usize },
+    DuplicateNumber { card_id: usize, number: u32 },
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Issue::MissingIds { from, to } if from == to => write!(f, "Card {} is missing", from),
+            Issue::MissingIds { from, to } => write!(f, "Cards {} to {} are missing", from, to),
+            Issue::DuplicateId { card_id, line } => {
+                write!(f, "Card {} on line {} is a duplicate", card_id, line)
+            }
+            Issue::OutOfOrder { card_id, line } => {
+                write!(f, "Card {} on line {} is out of order", card_id, line)
+            }
+            Issue::DuplicateNumber { card_id, number } => write!(
+                f,
+                "Card {} has winning number {} more than once",
+                card_id, number
+            ),
+        }
+    }
+}
+
+// Checks that the card ids go one by one starting from 1 and that winning numbers are unique.
+// The cards are expected in the order of the input lines.
+fn validate(cards: &[Card]) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut seen_ids: HashSet<usize> = HashSet::new();
+
+    for (index, card) in cards.iter().enumerate() {
+        let line = index + 1;
+        if !seen_ids.insert(card.id) {
+            issues.push(Issue::DuplicateId {
+                card_id: card.id,
+                line,
+            });
         }
+        if index > 0 && card.id < cards[index - 1].id {
+            issues.push(Issue::OutOfOrder {
+                card_id: card.id,
+                line,
+            });
+        }
+
+        let mut seen_numbers: HashSet<u32> = HashSet::new();
+        for &number in &card.winning_numbers {
+            if !seen_numbers.insert(number) {
+                issues.push(Issue::DuplicateNumber {
+                    card_id: card.id,
+                    number,
+                });
+            }
+        }
+    }
+
+    // Every gap is reported once, however many ids are missing in it
+    let mut ids: Vec<usize> = seen_ids.into_iter().collect();
+    ids.sort_unstable();
+    let mut previous = 0;
+    for id in ids {
+        if id > previous + 1 {
+            issues.push(Issue::MissingIds {
+                from: previous + 1,
+                to: id - 1,
+            });
+        }
+        previous = id;
+    }
+
+    issues
+}
+
+#[derive(Debug, PartialEq)]
+enum Error {
+    CopiesPastEnd { card_id: usize, matches: u32 },
+    // Copies can't be found by ids when several cards have the same id
+    DuplicateId { card_id: usize },
+    // Points of the card don't fit into u64 or can't be computed by the scoring rule
+    PointsOverflow { card_id: usize, matches: u32 },
+    // Number of copies of the card doesn't fit into u64
+    CountOverflow { card_id: usize },
+    // The answer doesn't fit into u64
+    TotalOverflow,
+    Parse(CardParseError),
+}
+
+impl From<CardParseError> for Error {
+    fn from(err: CardParseError) -> Self {
+        Error::Parse(err)
     }
+}
 
-    cards_count.iter().sum::<u32>()
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::CopiesPastEnd { card_id, matches } => write!(
+                f,
+                "Card {} has {} matches, but wins copies past the end of the table",
+                card_id, matches
+            ),
+            Error::DuplicateId { card_id } => write!(f, "Card {} is not unique", card_id),
+            Error::PointsOverflow { card_id, matches } => write!(
+                f,
+                "Points of card {} with {} matches overflow u64 or are undefined",
+                card_id, matches
+            ),
+            Error::CountOverflow { card_id } => {
+                write!(f, "Number of copies of card {} overflows u64", card_id)
+            }
+            Error::TotalOverflow => write!(f, "Total overflows u64"),
+            Error::Parse(err) => write!(f, "{}", err),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -42,84 +582,900 @@ struct Card {
     id: usize,
     winning_numbers: Vec<u32>,
     player_numbers: Vec<u32>,
+    // The same winning numbers, but fast to look up
+    winning_set: NumberSet,
+}
+
+// Numbers on the cards are small, so a bitset is used whenever all of them fit into it.
+#[derive(Debug, Clone)]
+enum NumberSet {
+    Bits(u128),
+    Hashed(HashSet<u32>),
+}
+
+impl NumberSet {
+    fn new(numbers: &[u32]) -> Self {
+        if numbers.iter().all(|&n| n < u128::BITS) {
+            let bits = numbers.iter().fold(0u128, |bits, &n| bits | (1 << n));
+            NumberSet::Bits(bits)
+        } else {
+            NumberSet::Hashed(numbers.iter().copied().collect())
+        }
+    }
+
+    fn contains(&self, number: u32) -> bool {
+        match self {
+            NumberSet::Bits(bits) => number < u128::BITS && bits & (1 << number) != 0,
+            NumberSet::Hashed(set) => set.contains(&number),
+        }
+    }
 }
 
 impl Card {
-    fn parse(line: &str) -> Card {
-        let mut parts = line.split(":");
-
-        let id_part = parts.next().expect("ID part is missing").trim();
-        let mut numbers_parts = parts
-            .next()
-            .expect("Numbers part is missing")
-            .trim()
-            .split("|");
-        let winning_numbers_part = numbers_parts
-            .next()
-            .expect("Winning numbers part is missing")
-            .trim();
-        let player_numbers_part = numbers_parts
-            .next()
-            .expect("Players numbers part is missing")
-            .trim();
-
-        let id = id_part
-            .chars()
-            .filter(|ch| ch.is_numeric())
-            .collect::<String>()
-            .parse::<usize>()
-            .expect("Failed to parse Card ID");
-
-        let winning_numbers = winning_numbers_part
-            .split(" ")
-            .filter(|chunk| !chunk.is_empty())
-            .map(|chunk| chunk.trim().parse::<u32>().unwrap())
-            .collect::<Vec<u32>>();
-
-        let player_numbers = player_numbers_part
-            .split(" ")
-            .filter(|chunk| !chunk.is_empty())
-            .map(|chunk| chunk.trim().parse::<u32>().unwrap())
-            .collect::<Vec<u32>>();
+    fn parse(line_number: usize, line: &str) -> Result<Card, CardParseError> {
+        // Offsets are in bytes, but columns are reported in chars
+        let error = |offset: usize, reason: ParseReason| CardParseError {
+            line: line_number,
+            column: line[..offset].chars().count() + 1,
+            reason,
+        };
+
+        let Some(colon) = line.find(':') else {
+            return Err(error(line.len(), ParseReason::MissingColon));
+        };
+
+        let id = match tokens(&line[..colon], 0).as_slice() {
+            [(_, "Card"), (offset, id)] => parse_digits(id)
+                .ok_or_else(|| error(*offset, ParseReason::MalformedId(id.to_string())))?,
+            _ => return Err(error(0, ParseReason::MalformedHeader)),
+        };
+
+        let numbers_offset = colon + 1;
+        let separators: Vec<usize> = line[numbers_offset..]
+            .match_indices('|')
+            .map(|(offset, _)| numbers_offset + offset)
+            .collect();
+        let separator = match separators.as_slice() {
+            [] => return Err(error(line.len(), ParseReason::MissingSeparator)),
+            [separator] => *separator,
+            [_, duplicate, ..] => return Err(error(*duplicate, ParseReason::DuplicateSeparator)),
+        };
 
+        let parse_numbers = |from: usize, to: usize| {
+            tokens(&line[from..to], from)
+                .into_iter()
+                .map(|(offset, token)| {
+                    parse_digits(token)
+                        .ok_or_else(|| error(offset, ParseReason::InvalidNumber(token.to_string())))
+                })
+                .collect::<Result<Vec<u32>, CardParseError>>()
+        };
+        let winning_numbers = parse_numbers(numbers_offset, separator)?;
+        let player_numbers = parse_numbers(separator + 1, line.len())?;
+
+        Ok(Card::new(id, winning_numbers, player_numbers))
+    }
+
+    fn new(id: usize, winning_numbers: Vec<u32>, player_numbers: Vec<u32>) -> Card {
+        let winning_set = NumberSet::new(&winning_numbers);
         Card {
             id,
             winning_numbers,
             player_numbers,
+            winning_set,
         }
     }
 
+    // Same as `matched_numbers().len()`, but without allocating, it runs for every card
     fn count_matches(&self) -> u32 {
-        let mut matches = 0;
-        for player_number in &self.player_numbers {
-            if self.winning_numbers.contains(player_number) {
-                matches += 1;
+        self.player_numbers
+            .iter()
+            .filter(|n| self.winning_set.contains(**n))
+            .count() as u32
+    }
+
+    // Player numbers that are winning, in the order they are written on the card
+    fn matched_numbers(&self) -> Vec<u32> {
+        self.player_numbers
+            .iter()
+            .copied()
+            .filter(|n| self.winning_set.contains(*n))
+            .collect()
+    }
+
+    fn count_points(&self, rule: &ScoringRule) -> Result<u64, Error> {
+        let matches = self.count_matches();
+        rule.points(matches).ok_or(Error::PointsOverflow {
+            card_id: self.id,
+            matches,
+        })
+    }
+}
+
+fn parse_cards(input: &str) -> Result<Vec<Card>, CardParseError> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| Card::parse(index + 1, line))
+        .collect()
+}
+
+// Splits the text by whitespace, every token comes with its byte offset (shifted by `base_offset`)
+fn tokens(text: &str, base_offset: usize) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut token_start: Option<usize> = None;
+
+    for (offset, ch) in text.char_indices() {
+        match (ch.is_whitespace(), token_start) {
+            (true, Some(start)) => {
+                tokens.push((base_offset + start, &text[start..offset]));
+                token_start = None;
+            }
+            (false, None) => token_start = Some(offset),
+            _ => {}
+        }
+    }
+    if let Some(start) = token_start {
+        tokens.push((base_offset + start, &text[start..]));
+    }
+
+    tokens
+}
+
+// Unlike `str::parse` does not accept a leading '+'
+fn parse_digits<T: std::str::FromStr>(token: &str) -> Option<T> {
+    if token.chars().all(|ch| ch.is_ascii_digit()) {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct CardParseError {
+    line: usize,
+    column: usize,
+    reason: ParseReason,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseReason {
+    MissingColon,
+    // The part before ':' is not "Card <id>"
+    MalformedHeader,
+    MalformedId(String),
+    MissingSeparator,
+    DuplicateSeparator,
+    InvalidNumber(String),
+}
+
+impl std::fmt::Display for CardParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Line {}, column {}: ", self.line, self.column)?;
+        match &self.reason {
+            ParseReason::MissingColon => write!(f, "expected ':' after the card id"),
+            ParseReason::MalformedHeader => write!(f, "expected \"Card <id>\""),
+            ParseReason::MalformedId(id) => write!(f, "invalid card id {:?}", id),
+            ParseReason::MissingSeparator => write!(f, "expected '|' between the numbers"),
+            ParseReason::DuplicateSeparator => write!(f, "unexpected second '|'"),
+            ParseReason::InvalidNumber(token) => write!(f, "invalid number {:?}", token),
+        }
+    }
+}
+
+mod expression {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Expr {
+        Number(u64),
+        // The number of matches
+        N,
+        Binary(Box<Expr>, char, Box<Expr>),
+    }
+
+    impl Expr {
+        // None on overflow, negative results and division by zero
+        pub fn eval(&self, n: u64) -> Option<u64> {
+            match self {
+                Expr::Number(value) => Some(*value),
+                Expr::N => Some(n),
+                Expr::Binary(left, op, right) => {
+                    let (left, right) = (left.eval(n)?, right.eval(n)?);
+                    match op {
+                        '+' => left.checked_add(right),
+                        '-' => left.checked_sub(right),
+                        '*' => left.checked_mul(right),
+                        '/' => left.checked_div(right),
+                        '^' => left.checked_pow(u32::try_from(right).ok()?),
+                        _ => unreachable!("Unknown operator {}", op),
+                    }
+                }
             }
         }
-        matches
     }
 
-    fn count_points(&self) -> u32 {
-        match self.count_matches() {
-            0 => 0,
-            n => 2u32.pow(n - 1),
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let chars: Vec<char> = text.chars().filter(|ch| !ch.is_whitespace()).collect();
+        let mut parser = Parser { chars, pos: 0 };
+        let expr = parser.parse_sum()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(ch) => Err(format!("unexpected {:?}", ch)),
+        }
+    }
+
+    // Recursive descent parser, `^` binds tighter than `*` and `/`, which bind tighter than `+` and `-`
+    struct Parser {
+        chars: Vec<char>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Option<char> {
+            self.chars.get(self.pos).copied()
+        }
+
+        fn parse_sum(&mut self) -> Result<Expr, String> {
+            let mut expr = self.parse_product()?;
+            while let Some(op @ ('+' | '-')) = self.peek() {
+                self.pos += 1;
+                expr = Expr::Binary(Box::new(expr), op, Box::new(self.parse_product()?));
+            }
+            Ok(expr)
+        }
+
+        fn parse_product(&mut self) -> Result<Expr, String> {
+            let mut expr = self.parse_power()?;
+            while let Some(op @ ('*' | '/')) = self.peek() {
+                self.pos += 1;
+                expr = Expr::Binary(Box::new(expr), op, Box::new(self.parse_power()?));
+            }
+            Ok(expr)
+        }
+
+        fn parse_power(&mut self) -> Result<Expr, String> {
+            let base = self.parse_atom()?;
+            if self.peek() == Some('^') {
+                self.pos += 1;
+                // Right associative: 2^3^2 = 2^(3^2)
+                let exponent = self.parse_power()?;
+                return Ok(Expr::Binary(Box::new(base), '^', Box::new(exponent)));
+            }
+            Ok(base)
+        }
+
+        fn parse_atom(&mut self) -> Result<Expr, String> {
+            match self.peek() {
+                Some('n') => {
+                    self.pos += 1;
+                    Ok(Expr::N)
+                }
+                Some('(') => {
+                    self.pos += 1;
+                    let expr = self.parse_sum()?;
+                    if self.peek() != Some(')') {
+                        return Err("expected ')'".to_string());
+                    }
+                    self.pos += 1;
+                    Ok(expr)
+                }
+                Some(ch) if ch.is_ascii_digit() => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+                        self.pos += 1;
+                    }
+                    let digits: String = self.chars[start..self.pos].iter().collect();
+                    let value = digits
+                        .parse()
+                        .map_err(|_| format!("number {} is too big", digits))?;
+                    Ok(Expr::Number(value))
+                }
+                Some(ch) => Err(format!("unexpected {:?}", ch)),
+                None => Err("unexpected end".to_string()),
+            }
         }
     }
 }
 
+// Small deterministic PRNG (xorshift64*), good enough to generate test inputs
+mod rng {
+    pub struct Rng(u64);
 
-#[test]
-fn should_compute_example() {
-    let input = "
+    impl Rng {
+        pub fn new(seed: u64) -> Self {
+            // The state must never be zero
+            Rng((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        // Uniformly distributed in 0..n (with a negligible bias for small n)
+        pub fn below(&mut self, n: u64) -> u64 {
+            self.next_u64() % n
+        }
+    }
+}
+
+// Generates random inputs with known answers, for stress and overflow testing
+mod generator {
+    use super::rng::Rng;
+    use std::collections::HashSet;
+
+    #[derive(Debug, Clone)]
+    pub struct Config {
+        pub cards: usize,
+        pub winning_size: usize,
+        pub player_size: usize,
+        // Numbers are taken from 1..=max_number
+        pub max_number: u32,
+        // Relative weights of 0, 1, 2, ... matches on a card
+        pub match_weights: Vec<u64>,
+        pub seed: u64,
+    }
+
+    impl Default for Config {
+        // Similar to the real puzzle input
+        fn default() -> Self {
+            Config {
+                cards: 200,
+                winning_size: 10,
+                player_size: 25,
+                max_number: 99,
+                match_weights: vec![8, 4, 3, 2, 2, 1, 1, 1, 1, 1, 1],
+                seed: 0,
+            }
+        }
+    }
+
+    impl Config {
+        // Parses `--name=value` arguments, missing ones keep their default values
+        pub fn from_args(args: &[String]) -> Result<Config, String> {
+            let mut config = Config::default();
+            for arg in args {
+                let invalid = || format!("Invalid argument: {}", arg);
+                let (name, value) = arg.split_once('=').ok_or_else(invalid)?;
+                match name {
+                    "--cards" => config.cards = value.parse().map_err(|_| invalid())?,
+                    "--winning" => config.winning_size = value.parse().map_err(|_| invalid())?,
+                    "--player" => config.player_size = value.parse().map_err(|_| invalid())?,
+                    "--max" => config.max_number = value.parse().map_err(|_| invalid())?,
+                    "--seed" => config.seed = value.parse().map_err(|_| invalid())?,
+                    "--weights" => {
+                        config.match_weights = value
+                            .split(',')
+                            .map(|weight| weight.parse())
+                            .collect::<Result<Vec<u64>, _>>()
+                            .map_err(|_| invalid())?
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(config)
+        }
+    }
+
+    pub struct Generated {
+        pub input: String,
+        // None if the answer doesn't fit into u64
+        pub part1: Option<u64>,
+        pub part2: Option<u64>,
+    }
+
+    pub fn generate(config: &Config) -> Result<Generated, String> {
+        let max_matches = config.winning_size.min(config.player_size);
+        if config.match_weights.len() > max_matches + 1 {
+            return Err(format!(
+                "A card can't have more than {} matches",
+                max_matches
+            ));
+        }
+        if config.match_weights.iter().all(|&weight| weight == 0) {
+            return Err("At least one weight must be positive".to_string());
+        }
+        // Enough numbers for both lists to have no matches at all
+        if (config.max_number as usize) < config.winning_size + config.player_size {
+            return Err("Max number is too small for the list sizes".to_string());
+        }
+
+        let mut rng = Rng::new(config.seed);
+        let width = config.max_number.to_string().len();
+        let id_width = config.cards.to_string().len();
+        let format_list = |numbers: &[u32]| {
+            numbers
+                .iter()
+                .map(|n| format!("{:>width$}", n, width = width))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+
+        let mut input = String::new();
+        let mut matches_list: Vec<usize> = Vec::with_capacity(config.cards);
+
+        for index in 0..config.cards {
+            // Cards never win copies past the end of the table
+            let remaining = config.cards - index - 1;
+            let matches = pick_weighted(&mut rng, &config.match_weights).min(remaining);
+            matches_list.push(matches);
+
+            let winning = pick_distinct(&mut rng, config, config.winning_size, &HashSet::new());
+            let excluded: HashSet<u32> = winning.iter().copied().collect();
+            let mut player =
+                pick_distinct(&mut rng, config, config.player_size - matches, &excluded);
+            player.extend_from_slice(&winning[..matches]);
+            shuffle(&mut rng, &mut player);
+
+            input.push_str(&format!(
+                "Card {:>id_width$}: {} | {}\n",
+                index + 1,
+                format_list(&winning),
+                format_list(&player),
+                id_width = id_width
+            ));
+        }
+
+        Ok(Generated {
+            input,
+            part1: expected_part1(&matches_list),
+            part2: expected_part2(&matches_list),
+        })
+    }
+
+    // The answers are computed from the generated matches, independently of the solution
+    fn expected_part1(matches_list: &[usize]) -> Option<u64> {
+        matches_list
+            .iter()
+            .try_fold(0u64, |total, &matches| match matches {
+                0 => Some(total),
+                n => total.checked_add(2u64.checked_pow(n as u32 - 1)?),
+            })
+    }
+
+    fn expected_part2(matches_list: &[usize]) -> Option<u64> {
+        let mut counts: Vec<u64> = vec![1; matches_list.len()];
+        for (index, &matches) in matches_list.iter().enumerate() {
+            for won in index + 1..=index + matches {
+                counts[won] = counts[won].checked_add(counts[index])?;
+            }
+        }
+        counts
+            .iter()
+            .try_fold(0u64, |total, &count| total.checked_add(count))
+    }
+
+    fn pick_weighted(rng: &mut Rng, weights: &[u64]) -> usize {
+        let mut pick = rng.below(weights.iter().sum());
+        for (value, &weight) in weights.iter().enumerate() {
+            if pick < weight {
+                return value;
+            }
+            pick -= weight;
+        }
+        unreachable!("The pick is below the sum of the weights")
+    }
+
+    fn pick_distinct(
+        rng: &mut Rng,
+        config: &Config,
+        count: usize,
+        excluded: &HashSet<u32>,
+    ) -> Vec<u32> {
+        let mut picked: Vec<u32> = Vec::with_capacity(count);
+        while picked.len() < count {
+            let number = 1 + rng.below(config.max_number as u64) as u32;
+            if !excluded.contains(&number) && !picked.contains(&number) {
+                picked.push(number);
+            }
+        }
+        picked
+    }
+
+    fn shuffle(rng: &mut Rng, numbers: &mut [u32]) {
+        for i in (1..numbers.len()).rev() {
+            numbers.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+    }
+}
+
+// Input from the example in the task
+#[cfg(test)]
+const EXAMPLE: &str = "\
 Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
 Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19
 Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1
 Card 4: 41 92 73 84 69 | 59 84 76 51 58  5 54 83
 Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36
-Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11
+Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11";
+
+#[test]
+fn should_compute_example() {
+    let input = EXAMPLE;
+
+    assert_eq!(compute_part1(input), Ok(13));
+    assert_eq!(compute_part2(input), Ok(30));
+}
+
+#[test]
+fn should_handle_copies_past_the_end() {
+    let input = "
+Card 1: 41 48 83 | 83 86 41
+Card 2: 13 32 20 | 61 30 13
+Card 3:  1 21 53 |  1 21 53
+"
+    .trim();
+
+    // Card 1: 2 matches, card 2: 1 match, card 3: 3 matches which run past the end
+    assert_eq!(compute_part2(input), Ok(1 + 2 + 4));
+    let options = Part2Options {
+        bounds: BoundsPolicy::Error,
+        ..Part2Options::default()
+    };
+    assert_eq!(
+        compute_part2_with(input, &options),
+        Err(Error::CopiesPastEnd {
+            card_id: 3,
+            matches: 3
+        })
+    );
+}
+
+// Card N wins copies of all the following cards, so the number of copies doubles with every card
+#[cfg(test)]
+fn doubling_cards(cards_count: usize) -> String {
+    (1..=cards_count)
+        .map(|id| {
+            let numbers = (1..=cards_count - id)
+                .map(|n| n.to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!("Card {}: 1000 {} | 1001 {}", id, numbers, numbers)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn should_report_overflows() {
+    let input = doubling_cards(66);
+
+    assert_eq!(
+        compute_part1(&input),
+        Err(Error::PointsOverflow {
+            card_id: 1,
+            matches: 65
+        })
+    );
+    assert_eq!(
+        compute_part2(&input),
+        Err(Error::CountOverflow { card_id: 65 })
+    );
+
+    // Without the first two cards the counts fit and sum up exactly to u64::MAX
+    let mut lines = input.lines().skip(2).collect::<Vec<&str>>();
+    assert_eq!(compute_part2(&lines.join("\n")), Ok(u64::MAX));
+
+    // So one more card makes the total overflow
+    lines.push("Card 67: 1000 | 1001");
+    assert_eq!(compute_part2(&lines.join("\n")), Err(Error::TotalOverflow));
+}
+
+#[test]
+fn should_match_numbers_with_sets() {
+    let small = Card::parse(1, "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53").unwrap();
+    assert!(matches!(small.winning_set, NumberSet::Bits(_)));
+    assert_eq!(small.matched_numbers(), vec![83, 86, 17, 48]);
+
+    let big = Card::parse(2, "Card 2: 1000 7 127 128 | 128 5 1000 127 99999").unwrap();
+    assert!(matches!(big.winning_set, NumberSet::Hashed(_)));
+    assert_eq!(big.matched_numbers(), vec![128, 1000, 127]);
+    assert_eq!(big.count_matches(), 3);
+}
+
+// Run with `cargo test --release -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn bench_matching() {
+    use std::time::Instant;
+
+    for max_number in [100, 10_000] {
+        // Cards with hundreds of numbers, winning numbers are the even ones
+        let lists: Vec<(Vec<u32>, Vec<u32>)> = (0..1000)
+            .map(|id| {
+                let winning = (0..300).map(|n| (n * 2 + id) % max_number).collect();
+                let player = (0..500).map(|n| (n * 3 + id) % max_number).collect();
+                (winning, player)
+            })
+            .collect();
+        let cards: Vec<Card> = lists
+            .iter()
+            .enumerate()
+            .map(|(id, (winning, player))| Card::new(id, winning.clone(), player.clone()))
+            .collect();
+
+        let started = Instant::now();
+        let linear: u32 = lists
+            .iter()
+            .map(|(winning, player)| player.iter().filter(|n| winning.contains(n)).count() as u32)
+            .sum();
+        let linear_time = started.elapsed();
+
+        let started = Instant::now();
+        let with_set: u32 = cards.iter().map(|card| card.count_matches()).sum();
+        let set_time = started.elapsed();
+
+        assert_eq!(linear, with_set);
+        println!(
+            "Numbers below {}: linear {:?}, set {:?}",
+            max_number, linear_time, set_time
+        );
+    }
+}
+
+#[test]
+fn should_report_parse_errors() {
+    let error = |line: &str, column, reason| {
+        let err = CardParseError {
+            line: 1,
+            column,
+            reason,
+        };
+        assert_eq!(Card::parse(1, line).unwrap_err(), err);
+    };
+
+    error("Card 1x2: 1 | 2", 6, ParseReason::MalformedId("1x2".into()));
+    error("Card +1: 1 | 2", 6, ParseReason::MalformedId("+1".into()));
+    error("Crad 1: 1 | 2", 1, ParseReason::MalformedHeader);
+    error("Card 1 1 | 2", 13, ParseReason::MissingColon);
+    error("Card 1: 1 2", 12, ParseReason::MissingSeparator);
+    error("Card 1: 1 | 2 | 3", 15, ParseReason::DuplicateSeparator);
+    error(
+        "Card 1: 1 a | 2",
+        11,
+        ParseReason::InvalidNumber("a".into()),
+    );
+    error(
+        "Card 1: 1 | 2 99999999999",
+        15,
+        ParseReason::InvalidNumber("99999999999".into()),
+    );
+
+    let input = "Card 1: 1 | 2\nCard 2: 1 | 2,3";
+    let err = CardParseError {
+        line: 2,
+        column: 13,
+        reason: ParseReason::InvalidNumber("2,3".into()),
+    };
+    assert_eq!(compute_part1(input), Err(Error::Parse(err)));
+}
+
+#[test]
+fn should_validate_cards() {
+    let input = "
+Card 1: 41 48 41 | 83 86 41
+Card 3: 13 32 20 | 61 30 13
+Card 2:  1 21 53 |  1 21 53
+Card 2:  1 21 53 | 10 21 53
+Card 6:  1 21 53 | 10 21 53
+"
+    .trim();
+    let cards = parse_cards(input).unwrap();
+    assert_eq!(
+        validate(&cards),
+        vec![
+            Issue::DuplicateNumber {
+                card_id: 1,
+                number: 41
+            },
+            Issue::OutOfOrder {
+                card_id: 2,
+                line: 3
+            },
+            Issue::DuplicateId {
+                card_id: 2,
+                line: 4
+            },
+            Issue::MissingIds { from: 4, to: 5 },
+        ]
+    );
+
+    let cards = parse_cards("Card 4000000000: 1 | 1").unwrap();
+    assert_eq!(
+        validate(&cards),
+        vec![Issue::MissingIds {
+            from: 1,
+            to: 3999999999
+        }]
+    );
+
+    let by_id = Part2Options {
+        indexing: Indexing::ById,
+        ..Part2Options::default()
+    };
+    assert_eq!(
+        compute_part2_with(input, &by_id),
+        Err(Error::DuplicateId { card_id: 2 })
+    );
+
+    // Card 1 wins card 2, card 2 wins cards 3 and 4 (missing), card 3 wins card 4 (missing)
+    let input = "
+Card 3: 13 32 20 | 61 30 13
+Card 2:  1 21 53 | 10 21 53
+Card 1: 41 48 41 | 83 86 41
+Card 5:  1 21 53 | 10 11 12
 "
     .trim();
+    assert_eq!(compute_part2_with(input, &by_id), Ok(1 + 2 + 3 + 1));
+
+    // By lines every card wins copies of the cards on the next lines
+    assert_eq!(compute_part2(input), Ok(1 + 2 + 3 + 6));
+}
+
+#[test]
+fn should_trace_copies() {
+    let input = EXAMPLE;
+
+    let traces = trace_part2(input, &Part2Options::default()).unwrap();
+    let instances: Vec<u64> = traces.iter().map(|t| t.instances).collect();
+    assert_eq!(instances, vec![1, 2, 4, 8, 14, 1]);
+    assert_eq!(traces[4].contributions, vec![(1, 1), (3, 4), (4, 8)]);
+    assert!(traces[5].contributions.is_empty());
+
+    let table = format_trace_table(&traces);
+    assert!(table.contains("     2 |            2 | 61 32                          | 1 (+1)\n"));
+    assert!(table.contains(
+        "     5 |           14 |                                | 1 (+1), 3 (+4), 4 (+8)\n"
+    ));
+    assert!(format_trace_dot(&traces).contains("    c4 -> c5 [label=\"8\"];\n"));
+    assert!(format_trace_mermaid(&traces).contains("    c4 -->|8| c5\n"));
+}
+
+#[test]
+fn should_score_with_rules() {
+    let input = EXAMPLE;
+
+    // Matches: 4, 2, 2, 1, 0, 0
+    let score = |rule: &str| compute_part1_with(input, &ScoringRule::parse(rule).unwrap());
+    assert_eq!(score("doubling"), Ok(8 + 2 + 2 + 1));
+    assert_eq!(score("linear"), Ok(4 + 2 + 2 + 1));
+    assert_eq!(score("fibonacci"), Ok(3 + 1 + 1 + 1));
+    assert_eq!(score("table:10,20,30,40"), Ok(40 + 20 + 20 + 10));
+    assert_eq!(score("expr:2^(n-1)"), Ok(8 + 2 + 2 + 1));
+    assert_eq!(score("expr:n * n + 1"), Ok(17 + 5 + 5 + 2));
+    assert_eq!(
+        score("table:1,2"),
+        Err(Error::PointsOverflow {
+            card_id: 1,
+            matches: 4
+        })
+    );
+    assert!(ScoringRule::parse("expr:n +").is_err());
+    assert!(ScoringRule::parse("squares").is_err());
+
+    let window = |window: &str| {
+        let options = Part2Options {
+            window: CopyWindow::parse(window).unwrap(),
+            ..Part2Options::default()
+        };
+        compute_part2_with(input, &options)
+    };
+    assert_eq!(window("scaled:1"), Ok(30));
+    // Every winning card gives copies of only one next card
+    assert_eq!(window("fixed:1"), Ok(1 + 2 + 3 + 4 + 5 + 1));
+    // Card 1 with 4 matches reaches past the end and gets clamped
+    assert_eq!(window("scaled:2"), Ok(1 + 2 + 4 + 8 + 16 + 16));
+}
+
+#[test]
+fn should_compute_backward_same_as_forward() {
+    let mut rng = rng::Rng::new(2023);
+
+    for _ in 0..200 {
+        let cards_count = 1 + rng.below(40) as usize;
+        let mut ids: Vec<usize> = (1..=cards_count).collect();
+        // Shuffle the ids and sometimes leave gaps between them
+        for i in (1..ids.len()).rev() {
+            ids.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        let gap = rng.below(3) as usize;
+        let input = ids
+            .iter()
+            .map(|id| {
+                let mut list = |size| {
+                    (0..size)
+                        .map(|_| (1 + rng.below(20)).to_string())
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                };
+                let (winning, player) = (list(5), list(8));
+                format!("Card {}: {} | {}", id * (1 + gap), winning, player)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        for indexing in [Indexing::ByLine, Indexing::ById] {
+            for window in [
+                CopyWindow::Scaled(1),
+                CopyWindow::Scaled(3),
+                CopyWindow::Fixed(2),
+            ] {
+                for bounds in [BoundsPolicy::Clamp, BoundsPolicy::Error] {
+                    let forward = Part2Options {
+                        indexing,
+                        window,
+                        bounds,
+                        ..Part2Options::default()
+                    };
+                    let backward = Part2Options {
+                        algorithm: Algorithm::Backward,
+                        ..forward.clone()
+                    };
+                    assert_eq!(
+                        compute_part2_with(&input, &forward),
+                        compute_part2_with(&input, &backward),
+                        "{:?}\n{}",
+                        forward,
+                        input
+                    );
+                }
+            }
+        }
+    }
+
+    // A count of cards overflows, or only the total does
+    let input = doubling_cards(66);
+    let fits = input.lines().skip(2).collect::<Vec<&str>>().join("\n");
+    let total_overflows = format!("{}\nCard 67: 1000 | 1001", fits);
+    for input in [input, fits, total_overflows] {
+        let backward = Part2Options {
+            algorithm: Algorithm::Backward,
+            ..Part2Options::default()
+        };
+        assert_eq!(compute_part2(&input), compute_part2_with(&input, &backward));
+    }
+}
+
+#[test]
+fn should_generate_inputs_with_known_answers() {
+    for seed in 0..20 {
+        let config = generator::Config {
+            seed,
+            ..generator::Config::default()
+        };
+        let generated = generator::generate(&config).unwrap();
+        assert_eq!(generated.input.lines().count(), config.cards);
+        assert_eq!(compute_part1(&generated.input).ok(), generated.part1);
+        assert_eq!(compute_part2(&generated.input).ok(), generated.part2);
+
+        let cards = parse_cards(&generated.input).unwrap();
+        assert!(validate(&cards).is_empty());
+    }
+
+    // The same seed gives the same input
+    let config = generator::Config::default();
+    assert_eq!(
+        generator::generate(&config).unwrap().input,
+        generator::generate(&config).unwrap().input
+    );
+
+    // Every card wins copies of the next 10 cards, so the counts overflow
+    let config = generator::Config {
+        cards: 100,
+        match_weights: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        ..generator::Config::default()
+    };
+    let generated = generator::generate(&config).unwrap();
+    assert_eq!(generated.part2, None);
+    assert!(matches!(
+        compute_part2(&generated.input),
+        Err(Error::CountOverflow { .. })
+    ));
 
-    assert_eq!(compute_part1(input), 13);
-    assert_eq!(compute_part2(input), 30);
+    let config = generator::Config {
+        winning_size: 2,
+        ..generator::Config::default()
+    };
+    assert!(generator::generate(&config).is_err());
 }
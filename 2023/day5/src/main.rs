@@ -1,91 +1,317 @@
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 fn main() {
     let input = include_str!("input.txt");
 
-    let result1 = compute_part1(input);
+    let issues = validate(input);
+    for issue in &issues {
+        eprintln!("Warning: {}", issue);
+    }
+    // `--strict` refuses to solve an almanac with any issues
+    if !issues.is_empty() && std::env::args().any(|arg| arg == "--strict") {
+        panic!(
+            "The almanac has {} issue(s), refusing to solve it",
+            issues.len()
+        );
+    }
+
+    let data = parse::parse(input).unwrap_or_else(|err| panic!("{}", err));
+    let pipeline = seed_to_location(data.maps.clone()).unwrap_or_else(|err| panic!("{}", err));
+
+    let result1 = compute_part1(input).unwrap_or_else(|err| panic!("{}", err));
     println!("Part1 Result: {}", result1);
 
-    let result2 = compute_part2(input);
+    // `--brute-force` checks every seed one by one, it takes minutes on the real input
+    let result2 = if std::env::args().any(|arg| arg == "--brute-force") {
+        compute_part2_brute_force(input)
+    } else if std::env::args().any(|arg| arg == "--composed") {
+        compute_part2_composed(input)
+    } else {
+        compute_part2(input)
+    }
+    .unwrap_or_else(|err| panic!("{}", err));
     println!("Part2 Result: {}", result2);
-}
 
+    // `--location=N` tells which seeds end up at location N, `--location=A..B` does the same for [A, B)
+    if let Some(location) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--location=").map(String::from))
+    {
+        let parse_location = |raw: &str| -> u64 {
+            raw.parse()
+                .unwrap_or_else(|_| panic!("Invalid location: {}", raw))
+        };
+        let seeds = match location.split_once("..") {
+            Some((start, end)) => {
+                pipeline.invert_interval(parse_location(start)..parse_location(end))
+            }
+            None => pipeline
+                .invert(parse_location(&location))
+                .into_iter()
+                .map(|seed| seed..seed + 1)
+                .collect(),
+        };
+        println!();
+        if seeds.is_empty() {
+            println!("No seed lands at location {}", location);
+        }
+        // Every interval comes from a single piece of each map, so it's mapped as a whole
+        for seed in seeds {
+            let location = pipeline.convert(seed.start);
+            if seed.end - seed.start == 1 {
+                println!("Seed {} lands at location {}", seed.start, location);
+            } else {
+                let locations = location..location + (seed.end - seed.start);
+                println!("Seeds {:?} land at locations {:?}", seed, locations);
+            }
+        }
+    }
 
-fn compute_part1(input: &str) -> u64 {
-    let data = parse::parse(input);
-    let Data {
-        seeds,
-        maps,
-    } = data;
+    // `--route=FROM-to-TO` shows which maps convert FROM to TO
+    if let Some(route) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--route=").map(String::from))
+    {
+        let Some((source, destination)) = route.split_once("-to-") else {
+            panic!("Invalid route: {}", route);
+        };
+        let pipeline = MapPipeline::resolve(data.maps.clone(), source, destination)
+            .unwrap_or_else(|err| panic!("{}", err));
+        println!();
+        for map in &pipeline.maps {
+            println!("{}", map.full_name);
+        }
+    }
 
-    let pipeline = MapPipeline { maps };
+    // `--trace=N` shows every step of seed N on its way to the location
+    if let Some(seed) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--trace=").map(String::from))
+    {
+        let seed: u64 = seed
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid seed: {}", seed));
+        println!();
+        print!("{}", format_trace(&pipeline.trace(seed)));
+    }
+
+    // `--json` prints the almanac as JSON, `--normalize` prints it with sorted and merged ranges
+    if std::env::args().any(|arg| arg == "--json") {
+        let json = serde_json::to_string_pretty(&data).unwrap_or_else(|err| panic!("{}", err));
+        println!();
+        println!("{}", json);
+    }
+    if std::env::args().any(|arg| arg == "--normalize") {
+        let mut normalized = data.clone();
+        normalized.normalize();
+        println!();
+        print!("{}", write::write(&normalized));
+    }
 
-    let mut lowest_location = u64::MAX;
-    for seed in seeds {
-        let result = pipeline.convert(seed);
-        if result < lowest_location {
-            lowest_location = result;
+    // `--plot=svg` prints the seed-to-location function as SVG, `--plot=ascii` as a sparkline
+    if let Some(format) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--plot=").map(String::from))
+    {
+        let composed = pipeline
+            .compose()
+            .unwrap_or_else(|| panic!("No maps to plot"));
+        let seed_ranges =
+            parse::seed_ranges(input, &data.seeds).unwrap_or_else(|err| panic!("{}", err));
+        println!();
+        match format.as_str() {
+            "svg" => print!("{}", plot::svg(&composed, &seed_ranges)),
+            "ascii" => print!("{}", plot::sparkline(&composed, &seed_ranges, 80)),
+            other => panic!("Unknown plot format: {}", other),
         }
     }
 
-    lowest_location
+    // `--table` prints the whole seed-to-location function
+    if std::env::args().any(|arg| arg == "--table") {
+        if let Some(composed) = pipeline.compose() {
+            println!();
+            print!("{}", composed.format_table());
+        }
+    }
 }
 
-fn compute_part2(input: &str) -> u64 {
-    let data = parse::parse(input);
-    let Data {
-        seeds,
-        maps,
-    } = data;
+fn compute_part1(input: &str) -> Result<u64, Error> {
+    let data = parse::parse(input)?;
+    let Data { seeds, maps } = data;
+
+    let pipeline = seed_to_location(maps)?;
+
+    Ok(compute_lowest_location(seeds, &pipeline))
+}
 
-    let pipeline = MapPipeline { maps };
+// Pushes whole seed intervals through the maps instead of single seeds,
+// the lowest location is the lowest start of the resulting intervals.
+fn compute_part2(input: &str) -> Result<u64, Error> {
+    let data = parse::parse(input)?;
+    let Data { seeds, maps } = data;
+
+    let pipeline = seed_to_location(maps)?;
+
+    let intervals = parse::seed_ranges(input, &seeds)?;
+
+    let lowest_location = pipeline
+        .convert_intervals(intervals)
+        .iter()
+        .map(|interval| interval.start)
+        .min()
+        .unwrap_or(u64::MAX);
+    Ok(lowest_location)
+}
+
+// Collapses the maps into one seed-to-location map first, then every seed range is looked up once.
+fn compute_part2_composed(input: &str) -> Result<u64, Error> {
+    let data = parse::parse(input)?;
+    let Data { seeds, maps } = data;
+
+    let pipeline = seed_to_location(maps)?;
+    let seed_ranges = parse::seed_ranges(input, &seeds)?;
+    let Some(composed) = pipeline.compose() else {
+        return Ok(seed_ranges
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| range.start)
+            .min()
+            .unwrap_or(u64::MAX));
+    };
+
+    let lowest_location = seed_ranges
+        .into_iter()
+        .filter_map(|range| composed.min_over(range))
+        .min()
+        .unwrap_or(u64::MAX);
+    Ok(lowest_location)
+}
+
+const BRUTE_FORCE_BLOCK: u64 = 4096;
+
+fn compute_part2_brute_force(input: &str) -> Result<u64, Error> {
+    let data = parse::parse(input)?;
+    let Data { seeds, maps } = data;
+
+    let pipeline = seed_to_location(maps)?;
 
     // This calculations takes a long time, so we'll use rayon to parallelize it.
-    let mut lowest_locations = seeds.par_chunks(2)
-        .map(|pair| {
-            let start = pair[0];
-            let length = pair[1];
+    let seed_ranges = parse::seed_ranges(input, &seeds)?;
+    let lowest_locations = seed_ranges
+        .into_par_iter()
+        .map(|range| {
+            let Range { start, end } = range;
 
+            // The seeds go in sorted blocks, so the batch conversion can sweep through the first map
             let mut lowest = u64::MAX;
-            for seed in start..(start + length) {
-                let location = pipeline.convert(seed);
-                if location < lowest {
-                    lowest = location;
-                }
+            let mut block = Vec::with_capacity(BRUTE_FORCE_BLOCK as usize);
+            for block_start in (start..end).step_by(BRUTE_FORCE_BLOCK as usize) {
+                block.clear();
+                block.extend(block_start..(block_start + BRUTE_FORCE_BLOCK).min(end));
+                pipeline.convert_batch(&mut block);
+                lowest = block.iter().copied().fold(lowest, u64::min);
             }
             lowest
         })
         .collect::<Vec<u64>>();
 
-    lowest_locations.sort();
-    lowest_locations[0]
+    Ok(lowest_locations.into_iter().min().unwrap_or(u64::MAX))
 }
 
-fn compute_lowest_location(seeds: Vec<u64>, maps: Vec<Map>) -> u64 {
-    let mut current_values = seeds;
-    for map in maps {
-        current_values = map.convert_many(&current_values);
+fn compute_lowest_location(mut seeds: Vec<u64>, pipeline: &MapPipeline) -> u64 {
+    // Sorted seeds let the first map be applied with a single sweep
+    seeds.sort();
+    pipeline.convert_batch(&mut seeds);
+    seeds.into_iter().min().unwrap_or(u64::MAX)
+}
+
+fn seed_to_location(maps: Vec<Map>) -> Result<MapPipeline, RouteError> {
+    MapPipeline::resolve(maps, "seed", "location")
+}
+
+#[derive(Debug, PartialEq)]
+enum Error {
+    Almanac(AlmanacError),
+    Route(RouteError),
+}
+
+impl From<AlmanacError> for Error {
+    fn from(err: AlmanacError) -> Self {
+        Error::Almanac(err)
     }
-    current_values.sort();
-    current_values[0]
 }
 
+impl From<RouteError> for Error {
+    fn from(err: RouteError) -> Self {
+        Error::Route(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Almanac(err) => write!(f, "{}", err),
+            Error::Route(err) => write!(f, "{}", err),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Data {
     seeds: Vec<u64>,
     maps: Vec<Map>,
 }
 
-#[derive(Debug)]
+impl Data {
+    // Every map gets sorted ranges without empty or overlapping ones, and the ranges
+    // that continue each other with the same offset are merged into one.
+    fn normalize(&mut self) {
+        for map in &mut self.maps {
+            map.normalize();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawMap")]
 struct Map {
     full_name: String,
     source_category: String,
     destination_category: String,
-    ranges: Vec<MapRange>
+    ranges: Vec<MapRange>,
+}
+
+// A map as it is stored in JSON, its ranges may be in any order and overlap
+#[derive(Deserialize)]
+struct RawMap {
+    full_name: String,
+    source_category: String,
+    destination_category: String,
+    ranges: Vec<MapRange>,
+}
+
+// The same checks as in `parse::parse`, so the lookups can rely on sorted, non-overlapping ranges
+impl TryFrom<RawMap> for Map {
+    type Error = String;
+
+    fn try_from(raw: RawMap) -> Result<Self, Self::Error> {
+        if let Some(range) = raw.ranges.iter().find(|range| range.overflows()) {
+            return Err(format!(
+                "Range {} {} {} of {} doesn't fit into u64",
+                range.destination_start, range.source_start, range.length, raw.full_name
+            ));
+        }
+        let mut map = Map {
+            full_name: raw.full_name,
+            source_category: raw.source_category,
+            destination_category: raw.destination_category,
+            ranges: raw.ranges,
+        };
+        map.resolve_overlaps();
+        Ok(map)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct MapRange {
     destination_start: u64,
     source_start: u64,
@@ -94,35 +320,387 @@ struct MapRange {
 
 impl MapRange {
     fn contains(&self, source: u64) -> bool {
-        self.source_start <= source && source < self.source_start + self.length
+        self.source_start <= source && source < self.source_end()
     }
 
     fn convert(&self, source: u64) -> u64 {
         let offset = source - self.source_start;
         self.destination_start + offset
     }
+
+    // Exclusive
+    fn source_end(&self) -> u64 {
+        self.source_start.saturating_add(self.length)
+    }
+
+    // The end of the source or the destination doesn't fit into u64
+    fn overflows(&self) -> bool {
+        self.source_start.checked_add(self.length).is_none()
+            || self.destination_start.checked_add(self.length).is_none()
+    }
+
+    // How far the destination is from the source
+    fn shift(&self) -> i128 {
+        self.destination_start as i128 - self.source_start as i128
+    }
+
+    fn identity(interval: Range<u64>) -> Self {
+        Self {
+            destination_start: interval.start,
+            source_start: interval.start,
+            length: interval.end - interval.start,
+        }
+    }
 }
 
 impl Map {
+    // Sorts the ranges by source_start and cuts off the numbers that are already covered by
+    // an earlier range, so the first matching range wins and the ranges don't overlap.
+    // All the earlier ranges start before the current one, so what they cover of it
+    // is always its beginning, up to the furthest end seen so far.
+    fn resolve_overlaps(&mut self) {
+        self.ranges.sort_by_key(|r| r.source_start);
+
+        let mut furthest_end = 0;
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            let source_start = range.source_start.max(furthest_end);
+            if source_start < range.source_end() {
+                ranges.push(MapRange {
+                    destination_start: range.convert(source_start),
+                    source_start,
+                    length: range.source_end() - source_start,
+                });
+            }
+            furthest_end = furthest_end.max(range.source_end());
+        }
+        self.ranges = ranges;
+    }
+
+    fn normalize(&mut self) {
+        self.resolve_overlaps();
+
+        let mut ranges: Vec<MapRange> = Vec::new();
+        for range in self.ranges.drain(..) {
+            match ranges.last_mut() {
+                Some(last)
+                    if range.source_start == last.source_end() && last.shift() == range.shift() =>
+                {
+                    last.length += range.length;
+                }
+                _ => ranges.push(range),
+            }
+        }
+        self.ranges = ranges;
+    }
+
     fn convert(&self, source: u64) -> u64 {
+        match self.find_range(source) {
+            Some(range) => range.convert(source),
+            None => source,
+        }
+    }
+
+    // Binary search over the ranges. It relies on them being sorted by source_start
+    // and not overlapping, which `resolve_overlaps` makes sure of.
+    fn find_range(&self, source: u64) -> Option<&MapRange> {
+        let index = self
+            .ranges
+            .partition_point(|range| range.source_start <= source);
+        index
+            .checked_sub(1)
+            .map(|i| &self.ranges[i])
+            .filter(|range| range.contains(source))
+    }
+
+    // Converts the values in place. Sorted values are converted with one sweep over the ranges,
+    // otherwise every value is looked up on its own.
+    fn convert_batch(&self, values: &mut [u64]) {
+        if !values.is_sorted() {
+            for value in values.iter_mut() {
+                *value = self.convert(*value);
+            }
+            return;
+        }
+
+        let mut index = 0;
+        for value in values.iter_mut() {
+            while index < self.ranges.len() && self.ranges[index].source_end() <= *value {
+                index += 1;
+            }
+            match self.ranges.get(index) {
+                Some(range) if range.contains(*value) => *value = range.convert(*value),
+                _ => {}
+            }
+        }
+    }
+
+    // Converts the whole interval [start, end) at once. The interval is split at the boundaries
+    // of the ranges, so every part is either shifted by one of the ranges or left as is.
+    fn convert_interval(&self, interval: Range<u64>) -> Vec<Range<u64>> {
+        self.split_interval(interval)
+            .into_iter()
+            .map(|piece| piece.destination_start..piece.destination_start + piece.length)
+            .collect()
+    }
+
+    // Splits [start, end) into pieces that are shifted by the same offset, pieces not covered
+    // by any range are returned with destination_start == source_start.
+    // Relies on the ranges being sorted by source_start.
+    fn split_interval(&self, interval: Range<u64>) -> Vec<MapRange> {
+        let mut pieces = Vec::new();
+        let mut cursor = interval.start;
+
         for range in &self.ranges {
-            if range.contains(source) {
-                return range.convert(source);
+            if cursor >= interval.end || range.source_start >= interval.end {
+                break;
+            }
+            if range.source_end() <= cursor {
+                continue;
+            }
+            // The part before the range is not covered by any range
+            if cursor < range.source_start {
+                pieces.push(MapRange::identity(cursor..range.source_start));
+                cursor = range.source_start;
+            }
+            let end = interval.end.min(range.source_end());
+            pieces.push(MapRange {
+                destination_start: range.convert(cursor),
+                source_start: cursor,
+                length: end - cursor,
+            });
+            cursor = end;
+        }
+
+        if cursor < interval.end {
+            pieces.push(MapRange::identity(cursor..interval.end));
+        }
+        pieces
+    }
+
+    fn min_over(&self, interval: Range<u64>) -> Option<u64> {
+        self.convert_interval(interval)
+            .iter()
+            .map(|converted| converted.start)
+            .min()
+    }
+
+    // All the sources that end up in [start, end), the regions that are not covered by
+    // any range map to themselves, so they are included as well.
+    fn invert_interval(&self, interval: Range<u64>) -> Vec<Range<u64>> {
+        let mut sources: Vec<Range<u64>> = self
+            .split_interval(0..u64::MAX)
+            .into_iter()
+            .filter_map(|piece| {
+                let destination_end = piece.destination_start + piece.length;
+                let start = interval.start.max(piece.destination_start);
+                let end = interval.end.min(destination_end);
+                if start >= end {
+                    return None;
+                }
+                let source_start = piece.source_start + (start - piece.destination_start);
+                Some(source_start..source_start + (end - start))
+            })
+            .collect();
+        sources.sort_by_key(|source| source.start);
+        sources
+    }
+
+    fn invert(&self, destination: u64) -> Vec<u64> {
+        self.invert_interval(destination..destination.saturating_add(1))
+            .into_iter()
+            .map(|source| source.start)
+            .collect()
+    }
+
+    // Builds a single map that does the same as applying `self` and then `next`.
+    // Identity pieces are left out and neighbouring pieces with the same offset are merged.
+    fn then(&self, next: &Map) -> Map {
+        let mut ranges: Vec<MapRange> = Vec::new();
+
+        for piece in self.split_interval(0..u64::MAX) {
+            let destination = piece.destination_start..piece.destination_start + piece.length;
+            for next_piece in next.split_interval(destination) {
+                let offset = next_piece.source_start - piece.destination_start;
+                let range = MapRange {
+                    destination_start: next_piece.destination_start,
+                    source_start: piece.source_start + offset,
+                    length: next_piece.length,
+                };
+                if range.destination_start == range.source_start {
+                    continue;
+                }
+                match ranges.last_mut() {
+                    Some(last)
+                        if last.source_end() == range.source_start
+                            && last.destination_start + last.length == range.destination_start =>
+                    {
+                        last.length += range.length;
+                    }
+                    _ => ranges.push(range),
+                }
             }
         }
-        source
+
+        Map {
+            full_name: format!(
+                "{}-to-{} map",
+                self.source_category, next.destination_category
+            ),
+            source_category: self.source_category.clone(),
+            destination_category: next.destination_category.clone(),
+            ranges,
+        }
     }
 
-    fn convert_many(&self, sources: &[u64]) -> Vec<u64> {
-        sources.iter().map(|s| self.convert(*s)).collect()
+    // The whole function as a table, including the regions that are not covered by any range.
+    fn format_table(&self) -> String {
+        let mut table = format!(
+            "{:>22} {:>22} {:>22} {:>22}\n",
+            format!("{} start", self.source_category),
+            format!("{} end", self.source_category),
+            format!("{} start", self.destination_category),
+            "offset",
+        );
+        for piece in self.split_interval(0..u64::MAX) {
+            let offset = piece.shift();
+            table.push_str(&format!(
+                "{:>22} {:>22} {:>22} {:>22}\n",
+                piece.source_start,
+                piece.source_end(),
+                piece.destination_start,
+                offset,
+            ));
+        }
+        table
     }
 }
 
+#[derive(Serialize, Deserialize)]
 struct MapPipeline {
     maps: Vec<Map>,
 }
 
+#[derive(Debug, PartialEq)]
+struct TraceStep {
+    category: String,
+    value: u64,
+    // The range that gave the value, None for the seed itself and where no range matched
+    range: Option<MapRange>,
+    // Where the previous value is inside the range, counted from its start
+    position: u64,
+}
+
+fn format_trace(steps: &[TraceStep]) -> String {
+    let narrative: Vec<String> = steps
+        .iter()
+        .map(|step| format!("{} {}", step.category, step.value))
+        .collect();
+    let mut output = format!("{}.\n\n", capitalize(&narrative.join(", ")));
+
+    output.push_str(&format!(
+        "{:<14} {:>12} {:>36} {:>12}\n",
+        "category", "value", "range", "position"
+    ));
+    for step in steps {
+        let range = match &step.range {
+            Some(range) => format!(
+                "{} {} {}",
+                range.destination_start, range.source_start, range.length
+            ),
+            None => "-".to_string(),
+        };
+        output.push_str(&format!(
+            "{:<14} {:>12} {:>36} {:>12}\n",
+            step.category, step.value, range, step.position
+        ));
+    }
+    output
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RouteError {
+    // No map starts at the category, so the target can't be reached
+    Gap {
+        category: String,
+    },
+    // The chain comes back to a category it has already passed
+    Cycle {
+        category: String,
+    },
+    // Several maps start at the same category
+    Ambiguous {
+        category: String,
+        destinations: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RouteError::Gap { category } => write!(f, "No map converts from {}", category),
+            RouteError::Cycle { category } => write!(f, "Maps run in a cycle through {}", category),
+            RouteError::Ambiguous {
+                category,
+                destinations,
+            } => write!(
+                f,
+                "Several maps convert from {}: to {}",
+                category,
+                destinations.join(", ")
+            ),
+        }
+    }
+}
+
 impl MapPipeline {
+    // Chains the maps by their categories, from `source` to `destination`.
+    // Maps that are not needed on the way are dropped, the order in the file doesn't matter.
+    fn resolve(maps: Vec<Map>, source: &str, destination: &str) -> Result<Self, RouteError> {
+        let mut by_source: HashMap<String, Vec<Map>> = HashMap::new();
+        for map in maps {
+            by_source
+                .entry(map.source_category.clone())
+                .or_default()
+                .push(map);
+        }
+
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut category = source.to_string();
+        while category != destination {
+            if !visited.insert(category.clone()) {
+                return Err(RouteError::Cycle { category });
+            }
+            let Some(mut next) = by_source.remove(&category) else {
+                return Err(RouteError::Gap { category });
+            };
+            if next.len() > 1 {
+                let destinations = next
+                    .iter()
+                    .map(|map| map.destination_category.clone())
+                    .collect();
+                return Err(RouteError::Ambiguous {
+                    category,
+                    destinations,
+                });
+            }
+            let map = next.remove(0);
+            category = map.destination_category.clone();
+            chain.push(map);
+        }
+
+        Ok(Self { maps: chain })
+    }
+
     fn convert(&self, source: u64) -> u64 {
         let mut current = source;
         for map in &self.maps {
@@ -130,111 +708,692 @@ impl MapPipeline {
         }
         current
     }
+
+    // Every value the seed goes through, starting with the seed itself
+    fn trace(&self, seed: u64) -> Vec<TraceStep> {
+        let mut steps = vec![TraceStep {
+            category: self
+                .maps
+                .first()
+                .map_or("seed".to_string(), |map| map.source_category.clone()),
+            value: seed,
+            range: None,
+            position: 0,
+        }];
+
+        let mut current = seed;
+        for map in &self.maps {
+            let range = map.find_range(current);
+            let position = range.map_or(0, |range| current - range.source_start);
+            current = map.convert(current);
+            steps.push(TraceStep {
+                category: map.destination_category.clone(),
+                value: current,
+                range: range.cloned(),
+                position,
+            });
+        }
+        steps
+    }
+
+    // Converts the values in place, the same as calling `convert` for each of them.
+    // Huge batches are split into chunks which are converted in parallel.
+    fn convert_batch(&self, values: &mut [u64]) {
+        const CHUNK: usize = 1 << 16;
+
+        if values.len() > CHUNK {
+            values
+                .par_chunks_mut(CHUNK)
+                .for_each(|chunk| self.convert_batch(chunk));
+            return;
+        }
+        for map in &self.maps {
+            map.convert_batch(values);
+        }
+    }
+
+    // Walks the maps backwards, gives every interval of seeds that ends up in [start, end).
+    fn invert_interval(&self, interval: Range<u64>) -> Vec<Range<u64>> {
+        let mut current = vec![interval];
+        for map in self.maps.iter().rev() {
+            current = current
+                .into_iter()
+                .filter(|interval| !interval.is_empty())
+                .flat_map(|interval| map.invert_interval(interval))
+                .collect();
+        }
+        current.sort_by_key(|interval| interval.start);
+        current
+    }
+
+    fn invert(&self, location: u64) -> Vec<u64> {
+        let mut current = vec![location];
+        for map in self.maps.iter().rev() {
+            current = current
+                .into_iter()
+                .flat_map(|value| map.invert(value))
+                .collect();
+        }
+        current.sort();
+        current
+    }
+
+    // Collapses all the maps into one, None if there are no maps at all.
+    fn compose(&self) -> Option<Map> {
+        let (first, rest) = self.maps.split_first()?;
+        Some(
+            rest.iter()
+                .fold(first.clone(), |composed, map| composed.then(map)),
+        )
+    }
+
+    fn convert_intervals(&self, intervals: Vec<Range<u64>>) -> Vec<Range<u64>> {
+        let mut current = intervals;
+        for map in &self.maps {
+            current = current
+                .into_iter()
+                .filter(|interval| !interval.is_empty())
+                .flat_map(|interval| map.convert_interval(interval))
+                .collect();
+        }
+        current
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Issue {
+    // The seeds of Part 2 come in pairs of start and length
+    OddSeedCount {
+        line: usize,
+        count: usize,
+    },
+    DuplicateMap {
+        name: String,
+        line: usize,
+        first_line: usize,
+    },
+    // The source ranges share some numbers, only one of the ranges is used for them
+    OverlappingRanges {
+        map: String,
+        line: usize,
+        other_line: usize,
+    },
+    // The source ranges touch each other and have the same offset, so they could be one range.
+    // Adjacent ranges with different offsets are normal and are not reported.
+    AdjacentRanges {
+        map: String,
+        line: usize,
+        other_line: usize,
+    },
+    // The end of the source or destination range doesn't fit into u64
+    OverflowingRange {
+        map: String,
+        line: usize,
+    },
 }
 
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Issue::OddSeedCount { line, count } => {
+                write!(
+                    f,
+                    "Line {}: odd number of seeds ({}), they can't be paired into ranges",
+                    line, count
+                )
+            }
+            Issue::DuplicateMap {
+                name,
+                line,
+                first_line,
+            } => {
+                write!(
+                    f,
+                    "Line {}: {} is already defined on line {}",
+                    line, name, first_line
+                )
+            }
+            Issue::OverlappingRanges {
+                map,
+                line,
+                other_line,
+            } => {
+                write!(
+                    f,
+                    "Line {}: range of {} overlaps the range on line {}",
+                    line, map, other_line
+                )
+            }
+            Issue::AdjacentRanges {
+                map,
+                line,
+                other_line,
+            } => {
+                write!(
+                    f,
+                    "Line {}: range of {} is adjacent to the range on line {}",
+                    line, map, other_line
+                )
+            }
+            Issue::OverflowingRange { map, line } => {
+                write!(f, "Line {}: range of {} overflows u64", line, map)
+            }
+        }
+    }
+}
 
+// Checks the raw almanac, so every issue can point to its line
+fn validate(input: &str) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut map_lines: HashMap<String, usize> = HashMap::new();
+    // The name of the map which is being read and its ranges with their lines
+    let mut current: Option<(String, Vec<(MapRange, usize)>)> = None;
+    // The line of the "seeds:" header and the number of seeds so far, the seeds
+    // may continue on the next lines just like the parser allows
+    let mut seeds: Option<(usize, usize)> = None;
+    let mut in_seeds = false;
+
+    for (index, text) in input.lines().enumerate() {
+        let line = index + 1;
+        match parse::classify(text) {
+            parse::Line::Blank => {}
+            parse::Line::Header {
+                name: "seeds",
+                rest,
+            } => {
+                seeds = Some((line, rest.split_whitespace().count()));
+                in_seeds = true;
+            }
+            parse::Line::Header { name, .. } => {
+                in_seeds = false;
+                if let Some((map, ranges)) = current.take() {
+                    validate_ranges(&map, ranges, &mut issues);
+                }
+                if let Some(&first_line) = map_lines.get(name) {
+                    issues.push(Issue::DuplicateMap {
+                        name: name.to_string(),
+                        line,
+                        first_line,
+                    });
+                } else {
+                    map_lines.insert(name.to_string(), line);
+                }
+                current = Some((name.to_string(), Vec::new()));
+            }
+            parse::Line::Data(text) if in_seeds => {
+                if let Some((_, count)) = seeds.as_mut() {
+                    *count += text.split_whitespace().count();
+                }
+            }
+            parse::Line::Data(text) => {
+                // Lines that can't be parsed are reported by the parser
+                if let (Some((_, ranges)), Ok(range)) =
+                    (current.as_mut(), parse::parse_range_numbers(text))
+                {
+                    ranges.push((range, line));
+                }
+            }
+        }
+    }
+    if let Some((map, ranges)) = current {
+        validate_ranges(&map, ranges, &mut issues);
+    }
+    if let Some((line, count)) = seeds {
+        if !count.is_multiple_of(2) {
+            issues.insert(0, Issue::OddSeedCount { line, count });
+        }
+    }
+
+    issues
+}
+
+fn validate_ranges(map: &str, mut ranges: Vec<(MapRange, usize)>, issues: &mut Vec<Issue>) {
+    for (range, line) in &ranges {
+        if range.overflows() {
+            issues.push(Issue::OverflowingRange {
+                map: map.to_string(),
+                line: *line,
+            });
+        }
+    }
+
+    ranges.sort_by_key(|(range, line)| (range.source_start, *line));
+    // The range that reaches the furthest so far, a range nested in it doesn't hide the overlaps
+    let mut furthest: Option<(&MapRange, usize)> = None;
+    for (range, line) in &ranges {
+        if let Some((other, other_line)) = furthest {
+            let map = map.to_string();
+            let same_offset = other.shift() == range.shift();
+            if range.source_start < other.source_end() {
+                issues.push(Issue::OverlappingRanges {
+                    map,
+                    line: *line,
+                    other_line,
+                });
+            } else if range.source_start == other.source_end() && same_offset {
+                issues.push(Issue::AdjacentRanges {
+                    map,
+                    line: *line,
+                    other_line,
+                });
+            }
+        }
+        if furthest.is_none_or(|(other, _)| range.source_end() > other.source_end()) {
+            furthest = Some((range, *line));
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct AlmanacError {
+    // The header of the section the line belongs to, if any
+    section: Option<String>,
+    line: usize,
+    reason: AlmanacErrorReason,
+}
+
+#[derive(Debug, PartialEq)]
+enum AlmanacErrorReason {
+    UnknownHeader(String),
+    // The header is not "<source>-to-<destination> map"
+    MalformedMapName(String),
+    InvalidNumber(String),
+    // A range is exactly 3 numbers: destination start, source start and length
+    WrongRangeSize(usize),
+    // The end of the source or destination range doesn't fit into u64
+    RangeOverflow(String),
+    // Numbers before the first header or text after a map header
+    UnexpectedToken(String),
+    DuplicateSeeds,
+    MissingSeeds,
+    // Part 2 reads the seeds as pairs of start and length
+    OddSeedCount(usize),
+}
+
+impl std::fmt::Display for AlmanacError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Line {}", self.line)?;
+        if let Some(section) = &self.section {
+            write!(f, " ({})", section)?;
+        }
+        write!(f, ": ")?;
+        match &self.reason {
+            AlmanacErrorReason::UnknownHeader(header) => write!(f, "unknown header {:?}", header),
+            AlmanacErrorReason::MalformedMapName(name) => {
+                write!(
+                    f,
+                    "expected \"<source>-to-<destination> map\", got {:?}",
+                    name
+                )
+            }
+            AlmanacErrorReason::InvalidNumber(token) => write!(f, "invalid number {:?}", token),
+            AlmanacErrorReason::RangeOverflow(range) => {
+                write!(f, "range {:?} doesn't fit into u64", range)
+            }
+            AlmanacErrorReason::WrongRangeSize(size) => {
+                write!(f, "expected 3 numbers in a range, got {}", size)
+            }
+            AlmanacErrorReason::UnexpectedToken(token) => write!(f, "unexpected {:?}", token),
+            AlmanacErrorReason::DuplicateSeeds => write!(f, "seeds are already defined"),
+            AlmanacErrorReason::MissingSeeds => write!(f, "no seeds defined"),
+            AlmanacErrorReason::OddSeedCount(count) => {
+                write!(
+                    f,
+                    "odd number of seeds ({}), they can't be paired into ranges",
+                    count
+                )
+            }
+        }
+    }
+}
 
 mod parse {
     use super::*;
 
-    #[derive(Debug)]
-    enum Item {
-        Seeds(Vec<u64>),
-        Map(Map),
+    // The section which the following lines belong to
+    enum Section {
+        Seeds,
+        Map,
+    }
+
+    pub enum Line<'a> {
+        Blank,
+        // "<name>: <rest>", e.g. "seeds: 79 14" or "seed-to-soil map:"
+        Header { name: &'a str, rest: &'a str },
+        // Numbers of the current section
+        Data(&'a str),
+    }
+
+    // Shared by the parser and the validator, so both see the same sections
+    pub fn classify(text: &str) -> Line<'_> {
+        let text = text.trim();
+        if text.is_empty() {
+            Line::Blank
+        } else if let Some((name, rest)) = text.split_once(':') {
+            Line::Header {
+                name: name.trim(),
+                rest,
+            }
+        } else {
+            Line::Data(text)
+        }
     }
 
-    pub fn parse(input: &str) -> Data {
+    // Goes line by line, so CRLF line endings and any number of blank lines are fine
+    pub fn parse(input: &str) -> Result<Data, AlmanacError> {
         let mut seeds: Option<Vec<u64>> = None;
         let mut maps: Vec<Map> = Vec::new();
+        let mut section: Option<(Section, String)> = None;
 
-        for raw_item in input.split("\n\n") {
-            match parse_item(raw_item) {
-                Item::Seeds(new_seeds) => {
-                    assert!(seeds.is_none());
-                    seeds = Some(new_seeds);
+        for (index, text) in input.lines().enumerate() {
+            let line = index + 1;
+            match classify(text) {
+                Line::Blank => {}
+                Line::Header { name, rest } => {
+                    let header = name.to_string();
+                    let error = |reason| AlmanacError {
+                        section: Some(header.clone()),
+                        line,
+                        reason,
+                    };
+                    if header == "seeds" {
+                        if seeds.is_some() {
+                            return Err(error(AlmanacErrorReason::DuplicateSeeds));
+                        }
+                        seeds = Some(parse_numbers(rest).map_err(error)?);
+                        section = Some((Section::Seeds, header));
+                    } else if header.ends_with(" map") {
+                        if !rest.trim().is_empty() {
+                            return Err(error(AlmanacErrorReason::UnexpectedToken(
+                                rest.trim().to_string(),
+                            )));
+                        }
+                        maps.push(parse_map_header(&header).map_err(error)?);
+                        section = Some((Section::Map, header));
+                    } else {
+                        return Err(error(AlmanacErrorReason::UnknownHeader(header.clone())));
+                    }
+                }
+                Line::Data(text) => {
+                    let error = |reason| AlmanacError {
+                        section: section.as_ref().map(|(_, header)| header.clone()),
+                        line,
+                        reason,
+                    };
+                    match section {
+                        Some((Section::Seeds, _)) => {
+                            let more_seeds = parse_numbers(text).map_err(error)?;
+                            seeds.get_or_insert_with(Vec::new).extend(more_seeds);
+                        }
+                        Some((Section::Map, _)) => {
+                            let range = parse_map_range(text).map_err(error)?;
+                            maps.last_mut().unwrap().ranges.push(range);
+                        }
+                        None => {
+                            return Err(error(AlmanacErrorReason::UnexpectedToken(
+                                text.to_string(),
+                            )))
+                        }
+                    }
                 }
-                Item::Map(new_map) => maps.push(new_map),
             }
         }
+
         let Some(seeds) = seeds else {
-            panic!("No seeds defined");
+            return Err(AlmanacError {
+                section: None,
+                line: input.lines().count(),
+                reason: AlmanacErrorReason::MissingSeeds,
+            });
         };
-
-        Data {
-            seeds,
-            maps,
+        for map in &mut maps {
+            map.resolve_overlaps();
         }
-    }
 
-    fn parse_item(raw_item: &str) -> Item {
-        let mut parts = raw_item.trim().split(":");
-        let header = parts.next().unwrap().trim();
-        let body = parts.next().unwrap().trim();
-        assert!(parts.next().is_none());
+        Ok(Data { seeds, maps })
+    }
 
-        if header == "seeds" {
-            let seeds = parse_seeds(body);
-            Item::Seeds(seeds)
-        } else if header.ends_with(" map") {
-            let map = parse_map(header, body);
-            Item::Map(map)
-        } else {
-            panic!("Unknown header: {}", header);
+    // The seeds as ranges for Part 2, the error points to the "seeds:" line of the input
+    pub fn seed_ranges(input: &str, seeds: &[u64]) -> Result<Vec<Range<u64>>, AlmanacError> {
+        if !seeds.len().is_multiple_of(2) {
+            let line = input
+                .lines()
+                .position(|text| matches!(classify(text), Line::Header { name: "seeds", .. }))
+                .map_or(0, |index| index + 1);
+            return Err(AlmanacError {
+                section: Some("seeds".to_string()),
+                line,
+                reason: AlmanacErrorReason::OddSeedCount(seeds.len()),
+            });
         }
+
+        let ranges = seeds
+            .chunks(2)
+            .map(|pair| pair[0]..pair[0].saturating_add(pair[1]))
+            .collect();
+        Ok(ranges)
     }
 
-    fn parse_seeds(raw_seeds: &str) -> Vec<u64> {
-        raw_seeds.split_whitespace()
-            .map(|s| s.parse().unwrap())
+    fn parse_numbers(text: &str) -> Result<Vec<u64>, AlmanacErrorReason> {
+        text.split_whitespace()
+            .map(|token| {
+                token
+                    .parse()
+                    .map_err(|_| AlmanacErrorReason::InvalidNumber(token.to_string()))
+            })
             .collect()
     }
 
-    fn parse_map(header: &str, body: &str) -> Map {
-        let mut parts = header.split(" ");
-        let from_to = parts.next().unwrap();
-        let mut from_to_parts = from_to.split("-to-");
-
-        let from = from_to_parts.next().unwrap();
-        let to = from_to_parts.next().unwrap();
-
-        let mut ranges = Vec::new();
-        for raw_range in body.split("\n") {
-            ranges.push(parse_map_range(raw_range));
+    // The ranges are added by the lines that follow the header
+    fn parse_map_header(header: &str) -> Result<Map, AlmanacErrorReason> {
+        let malformed = || AlmanacErrorReason::MalformedMapName(header.to_string());
+        let from_to = header.strip_suffix(" map").ok_or_else(malformed)?;
+        let (from, to) = from_to.split_once("-to-").ok_or_else(malformed)?;
+        if from.is_empty() || to.is_empty() || from.contains(' ') || to.contains(' ') {
+            return Err(malformed());
         }
 
-        ranges.sort_by_key(|r| r.source_start);
-
-        Map {
+        Ok(Map {
             full_name: header.to_string(),
             source_category: from.to_string(),
             destination_category: to.to_string(),
-            ranges,
+            ranges: Vec::new(),
+        })
+    }
+
+    // The conversions can't handle ranges that end past u64::MAX, so they are an error
+    fn parse_map_range(raw_range: &str) -> Result<MapRange, AlmanacErrorReason> {
+        let range = parse_range_numbers(raw_range)?;
+        if range.overflows() {
+            return Err(AlmanacErrorReason::RangeOverflow(raw_range.to_string()));
         }
+        Ok(range)
     }
 
-    fn parse_map_range(raw_range: &str) -> MapRange {
-        let mut parts = raw_range.split(" ");
-        let destination_start = parts.next().unwrap().parse().unwrap();
-        let source_start = parts.next().unwrap().parse().unwrap();
-        let length = parts.next().unwrap().parse().unwrap();
-        assert!(parts.next().is_none());
+    // Only checks that there are 3 numbers, the validator reports overflowing ranges on its own
+    pub fn parse_range_numbers(raw_range: &str) -> Result<MapRange, AlmanacErrorReason> {
+        let numbers = parse_numbers(raw_range)?;
+        let [destination_start, source_start, length] = numbers[..] else {
+            return Err(AlmanacErrorReason::WrongRangeSize(numbers.len()));
+        };
 
-        MapRange {
+        Ok(MapRange {
             destination_start,
             source_start,
             length,
+        })
+    }
+}
+
+mod write {
+    use super::*;
+
+    // The almanac in the same text format that `parse::parse` reads
+    pub fn write(data: &Data) -> String {
+        let seeds: Vec<String> = data.seeds.iter().map(|seed| seed.to_string()).collect();
+        let mut output = format!("seeds: {}\n", seeds.join(" "));
+
+        for map in &data.maps {
+            output.push_str(&format!("\n{}:\n", map.full_name));
+            for range in &map.ranges {
+                output.push_str(&format!(
+                    "{} {} {}\n",
+                    range.destination_start, range.source_start, range.length
+                ));
+            }
         }
+        output
     }
 }
 
+mod plot {
+    use super::*;
+
+    const WIDTH: f64 = 800.0;
+    const HEIGHT: f64 = 400.0;
+    const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    // The seed with the lowest location among all the seed ranges, with that location
+    pub fn lowest_seed(composed: &Map, seed_ranges: &[Range<u64>]) -> Option<(u64, u64)> {
+        seed_ranges
+            .iter()
+            .flat_map(|range| composed.split_interval(range.clone()))
+            .map(|piece| (piece.source_start, piece.destination_start))
+            .min_by_key(|&(_, location)| location)
+    }
+
+    // From the first seed to the last one of all the ranges
+    fn extent(seed_ranges: &[Range<u64>]) -> Option<Range<u64>> {
+        let start = seed_ranges.iter().map(|range| range.start).min()?;
+        let end = seed_ranges.iter().map(|range| range.end).max()?;
+        (start < end).then_some(start..end)
+    }
+
+    // Location against seed, every piece of the composed map is a line segment.
+    // The seed ranges are shaded and the lowest location is marked with a circle.
+    pub fn svg(composed: &Map, seed_ranges: &[Range<u64>]) -> String {
+        let mut output = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+            WIDTH, HEIGHT, WIDTH, HEIGHT
+        );
+        output.push_str(&format!(
+            "  <rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+            WIDTH, HEIGHT
+        ));
+
+        let Some(extent) = extent(seed_ranges) else {
+            output.push_str("</svg>\n");
+            return output;
+        };
+        let pieces = composed.split_interval(extent.clone());
+        let lowest = pieces
+            .iter()
+            .map(|piece| piece.destination_start)
+            .min()
+            .unwrap_or(0);
+        let highest = pieces
+            .iter()
+            .map(|piece| piece.destination_start + piece.length)
+            .max()
+            .unwrap_or(1);
+
+        let x =
+            |seed: u64| (seed - extent.start) as f64 / (extent.end - extent.start) as f64 * WIDTH;
+        let y = |location: u64| {
+            HEIGHT - (location - lowest) as f64 / (highest - lowest).max(1) as f64 * HEIGHT
+        };
+
+        for range in seed_ranges.iter().filter(|range| !range.is_empty()) {
+            output.push_str(&format!(
+                "  <rect class=\"seeds\" x=\"{:.2}\" y=\"0\" width=\"{:.2}\" height=\"{}\" fill=\"#cde\"/>\n",
+                x(range.start),
+                x(range.end) - x(range.start),
+                HEIGHT
+            ));
+        }
+        for piece in &pieces {
+            output.push_str(&format!(
+                "  <line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>\n",
+                x(piece.source_start),
+                y(piece.destination_start),
+                x(piece.source_end()),
+                y(piece.destination_start + piece.length)
+            ));
+        }
+        if let Some((seed, location)) = lowest_seed(composed, seed_ranges) {
+            output.push_str(&format!(
+                "  <circle class=\"minimum\" cx=\"{:.2}\" cy=\"{:.2}\" r=\"5\" fill=\"red\"/>\n",
+                x(seed),
+                y(location)
+            ));
+            output.push_str(&format!(
+                "  <text x=\"{:.2}\" y=\"{:.2}\" font-size=\"12\">seed {} → location {}</text>\n",
+                x(seed).min(WIDTH - 240.0) + 8.0,
+                (y(location) - 8.0).max(12.0),
+                seed,
+                location
+            ));
+        }
+        output.push_str("</svg>\n");
+        output
+    }
 
+    // Lowest location for each of `width` columns of the seeds as a sparkline,
+    // the line below shows the seed ranges with '-' and the lowest location with '^'
+    pub fn sparkline(composed: &Map, seed_ranges: &[Range<u64>], width: usize) -> String {
+        let Some(extent) = extent(seed_ranges) else {
+            return String::new();
+        };
+        let span = extent.end - extent.start;
+        let columns: Vec<Range<u64>> = (0..width as u64)
+            .map(|i| {
+                let start = extent.start + (span as u128 * i as u128 / width as u128) as u64;
+                let end = extent.start + (span as u128 * (i + 1) as u128 / width as u128) as u64;
+                start..end
+            })
+            .filter(|column| !column.is_empty())
+            .collect();
+
+        let minimums: Vec<u64> = columns
+            .iter()
+            .map(|column| composed.min_over(column.clone()).unwrap_or(u64::MAX))
+            .collect();
+        let lowest = minimums.iter().copied().min().unwrap_or(0);
+        let highest = minimums.iter().copied().max().unwrap_or(0);
+
+        let line: String = minimums
+            .iter()
+            .map(|&location| {
+                let level = (location - lowest) as u128 * (SPARKS.len() - 1) as u128
+                    / (highest - lowest).max(1) as u128;
+                SPARKS[level as usize]
+            })
+            .collect();
 
+        let lowest_seed = lowest_seed(composed, seed_ranges).map(|(seed, _)| seed);
+        let marks: String = columns
+            .iter()
+            .map(|column| {
+                if lowest_seed.is_some_and(|seed| column.contains(&seed)) {
+                    '^'
+                } else if seed_ranges
+                    .iter()
+                    .any(|range| range.start < column.end && column.start < range.end)
+                {
+                    '-'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
 
+        format!("{}\n{}\n", line, marks.trim_end())
+    }
+}
 
 #[test]
 fn should_compute_part1() {
-    let input =
-r#"
+    let input = r#"
 seeds: 79 14 55 13
 
 seed-to-soil map:
@@ -269,6 +1428,596 @@ humidity-to-location map:
 60 56 37
 56 93 4
 "#;
-    assert_eq!(compute_part1(input), 35);
-    assert_eq!(compute_part2(input), 46);
+    assert_eq!(compute_part1(input), Ok(35));
+    assert_eq!(compute_part2(input), Ok(46));
+    assert_eq!(compute_part2_composed(input), Ok(46));
+}
+
+#[cfg(test)]
+fn random_almanac(random: &mut impl FnMut(u64) -> u64) -> String {
+    let seeds = [random(100), random(30), random(100), random(30)];
+    let mut input = format!("seeds: {} {} {} {}", seeds[0], seeds[1], seeds[2], seeds[3]);
+    for name in ["seed-to-soil", "soil-to-water", "water-to-location"] {
+        input.push_str(&format!("\n\n{} map:", name));
+        // Source ranges may overlap, touch or be nested in each other, in any order
+        for _ in 0..1 + random(4) {
+            let length = 1 + random(30);
+            input.push_str(&format!("\n{} {} {}", random(100), random(80), length));
+        }
+    }
+    input
+}
+
+#[cfg(test)]
+fn lcg(seed: u64) -> impl FnMut(u64) -> u64 {
+    // Linear congruential generator, good enough to make up small almanacs
+    let mut state = seed;
+    move |below: u64| {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) % below
+    }
+}
+
+#[test]
+fn should_compute_part2_same_as_brute_force() {
+    let mut random = lcg(2023);
+
+    for _ in 0..300 {
+        let input = random_almanac(&mut random);
+        assert_eq!(
+            compute_part2(&input),
+            compute_part2_brute_force(&input),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn should_compose_pipeline_into_one_map() {
+    let mut random = lcg(42);
+
+    for _ in 0..300 {
+        let input = random_almanac(&mut random);
+        let pipeline = MapPipeline {
+            maps: parse::parse(&input).unwrap().maps,
+        };
+        let composed = pipeline.compose().unwrap();
+
+        assert_eq!(composed.full_name, "seed-to-location map");
+        for seed in 0..250 {
+            assert_eq!(
+                composed.convert(seed),
+                pipeline.convert(seed),
+                "seed {}\n{}",
+                seed,
+                input
+            );
+        }
+        assert_eq!(
+            compute_part2_composed(&input),
+            compute_part2(&input),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn should_invert_pipeline() {
+    let input = "seeds: 79 14 55 13\n\nseed-to-soil map:\n50 98 2\n52 50 48";
+    let pipeline = MapPipeline {
+        maps: parse::parse(input).unwrap().maps,
+    };
+    assert_eq!(pipeline.invert(51), vec![99]);
+    assert_eq!(pipeline.invert(52), vec![50]);
+    // Not covered by any range, so the seed maps to itself
+    assert_eq!(pipeline.invert(10), vec![10]);
+    assert_eq!(
+        pipeline.invert_interval(49..53),
+        vec![49..50, 50..51, 98..100]
+    );
+    // Seeds 50..98 go to 52..100, nothing but seed 100 itself lands at 100
+    assert_eq!(pipeline.invert(100), vec![100]);
+
+    let input = "seeds: 1 1\n\nseed-to-soil map:\n0 10 5";
+    let pipeline = MapPipeline {
+        maps: parse::parse(input).unwrap().maps,
+    };
+    // Seed 2 is not covered, seed 12 is shifted onto it
+    assert_eq!(pipeline.invert(2), vec![2, 12]);
+    assert_eq!(pipeline.invert(12), Vec::<u64>::new());
+
+    let mut random = lcg(7);
+    for _ in 0..300 {
+        let input = random_almanac(&mut random);
+        let pipeline = MapPipeline {
+            maps: parse::parse(&input).unwrap().maps,
+        };
+
+        for location in 0..100 {
+            let expected: Vec<u64> = (0..250)
+                .filter(|seed| pipeline.convert(*seed) == location)
+                .collect();
+            let seeds = pipeline.invert(location);
+            assert!(
+                seeds.iter().all(|seed| pipeline.convert(*seed) == location),
+                "{}",
+                input
+            );
+            let found: Vec<u64> = seeds.into_iter().filter(|seed| *seed < 250).collect();
+            assert_eq!(found, expected, "location {}\n{}", location, input);
+        }
+    }
+}
+
+#[test]
+fn should_resolve_maps_by_category() {
+    let input = "seeds: 79 14 55 13\n\n\
+        humidity-to-location map:\n60 56 37\n56 93 4\n\n\
+        seed-to-soil map:\n50 98 2\n52 50 48\n\n\
+        temperature-to-humidity map:\n0 69 1\n1 0 69\n\n\
+        soil-to-fertilizer map:\n0 15 37\n37 52 2\n39 0 15\n\n\
+        light-to-temperature map:\n45 77 23\n81 45 19\n68 64 13\n\n\
+        water-to-light map:\n88 18 7\n18 25 70\n\n\
+        fertilizer-to-water map:\n49 53 8\n0 11 42\n42 0 7\n57 7 4";
+    assert_eq!(compute_part1(input), Ok(35));
+    assert_eq!(compute_part2(input), Ok(46));
+
+    let maps = || parse::parse(input).unwrap().maps;
+    let pipeline = MapPipeline::resolve(maps(), "soil", "humidity").unwrap();
+    let names: Vec<&str> = pipeline
+        .maps
+        .iter()
+        .map(|map| map.destination_category.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec!["fertilizer", "water", "light", "temperature", "humidity"]
+    );
+    // Soil 81 -> fertilizer 81 -> water 81 -> light 74 -> temperature 78 -> humidity 78
+    assert_eq!(pipeline.convert(81), 78);
+
+    assert_eq!(
+        MapPipeline::resolve(maps(), "location", "seed").err(),
+        Some(RouteError::Gap {
+            category: "location".to_string()
+        }),
+    );
+
+    let with_cycle = "seeds: 1 1\n\nseed-to-soil map:\n0 0 1\n\nsoil-to-seed map:\n0 0 1";
+    assert_eq!(
+        MapPipeline::resolve(parse::parse(with_cycle).unwrap().maps, "seed", "location").err(),
+        Some(RouteError::Cycle {
+            category: "seed".to_string()
+        }),
+    );
+
+    let with_branch = "seeds: 1 1\n\nseed-to-soil map:\n0 0 1\n\nseed-to-water map:\n0 0 1";
+    assert_eq!(
+        MapPipeline::resolve(parse::parse(with_branch).unwrap().maps, "seed", "location").err(),
+        Some(RouteError::Ambiguous {
+            category: "seed".to_string(),
+            destinations: vec!["soil".to_string(), "water".to_string()],
+        }),
+    );
+
+    // The compute functions return the error instead of panicking
+    let without_location = "seeds: 1 2\n\nseed-to-soil map:\n1 2 3";
+    let gap = Error::Route(RouteError::Gap {
+        category: "soil".to_string(),
+    });
+    assert_eq!(compute_part1(without_location), Err(gap));
+    assert!(compute_part2(without_location).is_err());
+    assert!(compute_part2_composed(without_location).is_err());
+    assert!(compute_part2_brute_force(without_location).is_err());
+}
+
+#[test]
+fn should_validate_almanac() {
+    let input = "seeds: 79 14 55\n\
+        \n\
+        seed-to-soil map:\n\
+        50 98 2\n\
+        52 10 48\n\
+        0 20 5\n\
+        100 58 3\n\
+        \n\
+        seed-to-soil map:\n\
+        0 18446744073709551610 10\n";
+
+    assert_eq!(
+        validate(input),
+        vec![
+            Issue::OddSeedCount { line: 1, count: 3 },
+            Issue::OverlappingRanges {
+                map: "seed-to-soil map".to_string(),
+                line: 6,
+                other_line: 5
+            },
+            Issue::AdjacentRanges {
+                map: "seed-to-soil map".to_string(),
+                line: 7,
+                other_line: 5
+            },
+            Issue::DuplicateMap {
+                name: "seed-to-soil map".to_string(),
+                line: 9,
+                first_line: 3
+            },
+            Issue::OverflowingRange {
+                map: "seed-to-soil map".to_string(),
+                line: 10
+            },
+        ],
+    );
+
+    let input = "seeds: 1 2\n3\n\nseed-to-soil map:\n1 2 3";
+    assert_eq!(
+        validate(input),
+        vec![Issue::OddSeedCount { line: 1, count: 3 }]
+    );
+    let input = "seeds: 1\n2\n\nseed-to-soil map:\n1 2 3";
+    assert_eq!(validate(input), vec![]);
+
+    // Spaces around the header name are fine for the parser, so they are for the validator
+    let input = "seeds : 1 2 3\n\nseed-to-location map:\n1 2 3";
+    assert_eq!(
+        validate(input),
+        vec![Issue::OddSeedCount { line: 1, count: 3 }]
+    );
+    let err = compute_part2(input).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 1 (seeds): odd number of seeds (3), they can't be paired into ranges"
+    );
+}
+
+#[test]
+fn should_parse_crlf_and_blank_lines() {
+    let input = "\r\n\r\nseeds: 79 14\r\n55 13\r\n\r\n\r\n\
+        seed-to-soil map:\r\n50 98 2\r\n\r\n52 50 48\r\n\r\n\
+        soil-to-location map:\r\n0 15 37\r\n";
+    let data = parse::parse(input).unwrap();
+    assert_eq!(data.seeds, vec![79, 14, 55, 13]);
+    assert_eq!(data.maps.len(), 2);
+    assert_eq!(data.maps[0].ranges.len(), 2);
+    assert_eq!(data.maps[1].destination_category, "location");
+}
+
+#[test]
+fn should_report_almanac_errors() {
+    let error = |input: &str| parse::parse(input).err().unwrap();
+
+    assert_eq!(
+        error("seeds: 1 2\n\nseed-to-soil map:\n1 2 x3"),
+        AlmanacError {
+            section: Some("seed-to-soil map".to_string()),
+            line: 4,
+            reason: AlmanacErrorReason::InvalidNumber("x3".to_string()),
+        },
+    );
+    assert_eq!(
+        error("seeds: 1 2\n\nseed-to-soil map:\n1 2 3\n4 5").to_string(),
+        "Line 5 (seed-to-soil map): expected 3 numbers in a range, got 2",
+    );
+    assert_eq!(
+        error("seeds: 1 2\n\nfarm map:\n1 2 3").to_string(),
+        "Line 3 (farm map): expected \"<source>-to-<destination> map\", got \"farm map\"",
+    );
+    assert_eq!(
+        error("seeds: 1 2\nsoil:\n1 2 3").to_string(),
+        "Line 2 (soil): unknown header \"soil\"",
+    );
+    assert_eq!(
+        error("1 2 3\nseeds: 1 2").to_string(),
+        "Line 1: unexpected \"1 2 3\""
+    );
+    assert_eq!(
+        error("seeds: 1 2\nseeds: 3 4").reason,
+        AlmanacErrorReason::DuplicateSeeds
+    );
+    assert_eq!(
+        error("seed-to-soil map:\n1 2 3").reason,
+        AlmanacErrorReason::MissingSeeds
+    );
+
+    let odd = "\nseeds: 1 2 3\n\nseed-to-location map:\n1 2 3";
+    assert_eq!(compute_part1(odd), Ok(1));
+    let err = Error::Almanac(AlmanacError {
+        section: Some("seeds".to_string()),
+        line: 2,
+        reason: AlmanacErrorReason::OddSeedCount(3),
+    });
+    assert_eq!(compute_part2(odd), Err(err));
+    assert!(compute_part2_composed(odd).is_err());
+    assert!(compute_part2_brute_force(odd).is_err());
+
+    let overflowing = "seeds: 9 1\n\nseed-to-location map:\n18446744073709551610 0 10";
+    let err = AlmanacError {
+        section: Some("seed-to-location map".to_string()),
+        line: 4,
+        reason: AlmanacErrorReason::RangeOverflow("18446744073709551610 0 10".to_string()),
+    };
+    assert_eq!(compute_part1(overflowing), Err(Error::Almanac(err)));
+    assert!(compute_part2(overflowing).is_err());
+}
+
+#[test]
+fn should_convert_batch_same_as_one_by_one() {
+    let mut random = lcg(11);
+
+    for _ in 0..300 {
+        let input = random_almanac(&mut random);
+        let pipeline = MapPipeline {
+            maps: parse::parse(&input).unwrap().maps,
+        };
+
+        let mut sorted: Vec<u64> = (0..250).collect();
+        let mut shuffled: Vec<u64> = (0..250).map(|_| random(250)).collect();
+        let expected_sorted: Vec<u64> = sorted.iter().map(|seed| pipeline.convert(*seed)).collect();
+        let expected_shuffled: Vec<u64> = shuffled
+            .iter()
+            .map(|seed| pipeline.convert(*seed))
+            .collect();
+
+        pipeline.convert_batch(&mut sorted);
+        pipeline.convert_batch(&mut shuffled);
+        assert_eq!(sorted, expected_sorted, "{}", input);
+        assert_eq!(shuffled, expected_shuffled, "{}", input);
+    }
+}
+
+// Run with `cargo test --release -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn bench_convert_batch() {
+    use std::time::Instant;
+
+    let data = parse::parse(include_str!("input.txt")).unwrap();
+    let pipeline = seed_to_location(data.maps).unwrap();
+    let start = data.seeds[0];
+
+    for size in [10_000, 1_000_000, 10_000_000] {
+        let seeds: Vec<u64> = (start..start + size).collect();
+
+        let started = Instant::now();
+        let one_by_one: Vec<u64> = seeds.iter().map(|seed| pipeline.convert(*seed)).collect();
+        let one_by_one_time = started.elapsed();
+
+        let mut batch = seeds.clone();
+        let started = Instant::now();
+        pipeline.convert_batch(&mut batch);
+        let batch_time = started.elapsed();
+
+        assert_eq!(one_by_one, batch);
+        println!(
+            "{} seeds: one by one {:?}, batch {:?}",
+            size, one_by_one_time, batch_time
+        );
+    }
+}
+
+#[test]
+fn should_trace_seed() {
+    let input = "seeds: 79 14 55 13\n\n\
+        seed-to-soil map:\n50 98 2\n52 50 48\n\n\
+        soil-to-fertilizer map:\n0 15 37\n37 52 2\n39 0 15";
+    let maps = parse::parse(input).unwrap().maps;
+    let pipeline = MapPipeline::resolve(maps, "seed", "fertilizer").unwrap();
+
+    let steps = pipeline.trace(79);
+    assert_eq!(
+        steps,
+        vec![
+            TraceStep {
+                category: "seed".to_string(),
+                value: 79,
+                range: None,
+                position: 0
+            },
+            TraceStep {
+                category: "soil".to_string(),
+                value: 81,
+                range: Some(MapRange {
+                    destination_start: 52,
+                    source_start: 50,
+                    length: 48
+                }),
+                position: 29,
+            },
+            TraceStep {
+                category: "fertilizer".to_string(),
+                value: 81,
+                range: None,
+                position: 0
+            },
+        ],
+    );
+    assert!(format_trace(&steps).starts_with("Seed 79, soil 81, fertilizer 81.\n"));
+}
+
+#[test]
+fn should_round_trip_almanac() {
+    let mut random = lcg(49);
+
+    for _ in 0..100 {
+        let input = random_almanac(&mut random);
+        let data = parse::parse(&input).unwrap();
+
+        assert_eq!(parse::parse(&write::write(&data)).unwrap(), data);
+
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<Data>(&json).unwrap(), data);
+
+        let mut normalized = data.clone();
+        normalized.normalize();
+        assert_eq!(
+            parse::parse(&write::write(&normalized)).unwrap(),
+            normalized
+        );
+
+        let pipeline = MapPipeline { maps: data.maps };
+        let normalized_pipeline = MapPipeline {
+            maps: normalized.maps,
+        };
+        for seed in 0..250 {
+            assert_eq!(
+                normalized_pipeline.convert(seed),
+                pipeline.convert(seed),
+                "{}",
+                input
+            );
+        }
+    }
+
+    // JSON is not trusted to have sorted, non-overlapping ranges
+    let json = r#"{"seeds": [55, 1], "maps": [{
+        "full_name": "seed-to-location map",
+        "source_category": "seed",
+        "destination_category": "location",
+        "ranges": [
+            {"destination_start": 100, "source_start": 50, "length": 10},
+            {"destination_start": 200, "source_start": 0, "length": 10},
+            {"destination_start": 300, "source_start": 20, "length": 10},
+            {"destination_start": 400, "source_start": 70, "length": 10},
+            {"destination_start": 500, "source_start": 55, "length": 10}
+        ]
+    }]}"#;
+    let from_json: Data = serde_json::from_str(json).unwrap();
+    let from_text = parse::parse(&write::write(&from_json)).unwrap();
+    assert_eq!(from_json, from_text);
+    let pipeline = seed_to_location(from_json.maps.clone()).unwrap();
+    assert_eq!(pipeline.convert(55), 105);
+    assert_eq!(pipeline.convert(62), 507);
+    assert_eq!(
+        serde_json::from_str::<Data>(&serde_json::to_string(&from_json).unwrap()).unwrap(),
+        from_json
+    );
+
+    let overflowing = json.replace(
+        "\"destination_start\": 400",
+        "\"destination_start\": 18446744073709551610",
+    );
+    assert!(serde_json::from_str::<Data>(&overflowing).is_err());
+}
+
+#[test]
+fn should_normalize_map() {
+    let input = "seeds: 1 1\n\nseed-to-soil map:\n15 10 5\n10 5 5\n0 30 0\n100 20 5\n23 18 4";
+    let mut data = parse::parse(input).unwrap();
+    data.normalize();
+
+    // 20..22 is already covered by 18..22, so the last range starts at 22
+    assert_eq!(
+        write::write(&data),
+        "seeds: 1 1\n\nseed-to-soil map:\n10 5 10\n23 18 4\n102 22 3\n",
+    );
+
+    // Overlapping ranges are resolved first, so the result never overlaps
+    let input = "seeds: 1 1\n\nseed-to-soil map:\n100 0 20\n120 20 5";
+    let mut data = parse::parse(input).unwrap();
+    data.maps[0].ranges.push(MapRange {
+        destination_start: 200,
+        source_start: 5,
+        length: 5,
+    });
+    data.normalize();
+    assert_eq!(
+        write::write(&data),
+        "seeds: 1 1\n\nseed-to-soil map:\n100 0 25\n"
+    );
+}
+
+#[test]
+fn should_plot_composed_map() {
+    let input = "seeds: 79 14 55 13\n\nseed-to-soil map:\n50 98 2\n52 50 48\n\n\
+        soil-to-location map:\n0 15 37\n37 52 2\n39 0 15";
+    let pipeline =
+        MapPipeline::resolve(parse::parse(input).unwrap().maps, "seed", "location").unwrap();
+    let composed = pipeline.compose().unwrap();
+    let seed_ranges = vec![79..93, 55..68];
+
+    // Seed 55 -> soil 57 -> location 57 is the lowest
+    assert_eq!(plot::lowest_seed(&composed, &seed_ranges), Some((55, 57)));
+
+    let svg = plot::svg(&composed, &seed_ranges);
+    assert!(svg.starts_with("<svg"));
+    assert_eq!(svg.matches("class=\"seeds\"").count(), 2);
+    assert_eq!(svg.matches("class=\"minimum\"").count(), 1);
+    assert!(svg.contains("seed 55 → location 57"));
+
+    // One column per seed from 55 to 93
+    let sparkline = plot::sparkline(&composed, &seed_ranges, 38);
+    let lines: Vec<&str> = sparkline.lines().collect();
+    assert_eq!(lines[0].chars().count(), 38);
+    assert_eq!(lines[1], "^------------           --------------");
+}
+
+#[test]
+fn should_use_first_matching_range() {
+    let input = "seeds: 1 1\n\nseed-to-soil map:\n100 0 20\n200 5 5";
+    let pipeline = MapPipeline {
+        maps: parse::parse(input).unwrap().maps,
+    };
+    assert_eq!(pipeline.convert(15), 115);
+
+    let input = "seeds: 1 1\n\nseed-to-soil map:\n200 5 15\n100 0 10";
+    let pipeline = MapPipeline {
+        maps: parse::parse(input).unwrap().maps,
+    };
+    assert_eq!(pipeline.convert(7), 107);
+    assert_eq!(pipeline.convert(12), 207);
+    assert_eq!(pipeline.trace(7)[1].value, 107);
+
+    // Every lookup agrees with checking the ranges one by one in the order of source_start
+    let mut random = lcg(5);
+    for _ in 0..300 {
+        let input = random_almanac(&mut random);
+        let raw_maps: Vec<Vec<Vec<u64>>> = input
+            .split("\n\n")
+            .skip(1)
+            .map(|section| {
+                let mut ranges: Vec<Vec<u64>> = section
+                    .lines()
+                    .skip(1)
+                    .map(|line| line.split(' ').map(|n| n.parse().unwrap()).collect())
+                    .collect();
+                ranges.sort_by_key(|range| range[1]);
+                ranges
+            })
+            .collect();
+        let expected = |seed: u64| {
+            raw_maps.iter().fold(seed, |value, ranges| {
+                match ranges.iter().find(|r| r[1] <= value && value < r[1] + r[2]) {
+                    Some(r) => r[0] + (value - r[1]),
+                    None => value,
+                }
+            })
+        };
+
+        let pipeline = MapPipeline {
+            maps: parse::parse(&input).unwrap().maps,
+        };
+        let mut batch: Vec<u64> = (0..250).collect();
+        pipeline.convert_batch(&mut batch);
+        for seed in 0..250 {
+            assert_eq!(
+                pipeline.convert(seed),
+                expected(seed),
+                "seed {}\n{}",
+                seed,
+                input
+            );
+            assert_eq!(
+                batch[seed as usize],
+                expected(seed),
+                "seed {}\n{}",
+                seed,
+                input
+            );
+        }
+    }
 }